@@ -17,13 +17,22 @@ import Friends from './pages/Friends';
 // Home Page Component
 const Home = () => {
     const [tracks, setTracks] = useState([]);
+    const [nextCursor, setNextCursor] = useState(null);
     const { handleSelectTrack, currentTrackId } = useOutletContext();
 
-    useEffect(() => {
-        fetch('/api/tracks')
+    const loadTracks = (cursor = null) => {
+        const url = cursor ? `/api/tracks?cursor=${encodeURIComponent(cursor)}` : '/api/tracks';
+        fetch(url)
             .then(res => res.json())
-            .then(data => setTracks(data))
+            .then(data => {
+                setTracks(prev => cursor ? [...prev, ...data.items] : data.items);
+                setNextCursor(data.next_cursor);
+            })
             .catch(err => console.error("Failed to fetch tracks:", err));
+    };
+
+    useEffect(() => {
+        loadTracks();
     }, []);
 
     const onSelect = (track) => {
@@ -38,6 +47,14 @@ const Home = () => {
                 onSelect={onSelect} 
                 currentTrackId={currentTrackId} 
             />
+            {nextCursor && (
+                <button
+                    onClick={() => loadTracks(nextCursor)}
+                    className="mt-6 px-4 py-2 rounded bg-gray-800 text-white hover:bg-gray-700"
+                >
+                    Load more
+                </button>
+            )}
         </div>
     );
 };
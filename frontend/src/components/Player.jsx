@@ -1,24 +1,70 @@
 import React, { useRef, useEffect } from 'react';
+import Hls from 'hls.js';
 import { SkipBack, SkipForward } from 'lucide-react';
 
-const Player = ({ currentTrack, isPlaying, onPlayPause, onNext, onPrev }) => {
+// Progressive streaming by default. HLS decodes the whole file first, so it's only
+// worth it when asked for, or for lossless sources big enough to need smaller renditions
+const isLossless = (codec) => codec === 'flac' || codec === 'alac' || codec?.startsWith('pcm');
+
+const Player = ({ currentTrack, isPlaying, onPlayPause, onNext, onPrev, adaptive = false }) => {
     const audioRef = useRef(null);
+    const hlsRef = useRef(null);
 
     const [currentTime, setCurrentTime] = React.useState(0);
     const [duration, setDuration] = React.useState(0);
 
     useEffect(() => {
         if (currentTrack) {
-            audioRef.current.src = `/api/stream/${currentTrack.id}`;
-            // Reset state for new track
+            const audio = audioRef.current;
+            const streamUrl = `/api/stream/${currentTrack.id}`;
+            const hlsUrl = `${streamUrl}/hls/master.m3u8`;
+
+            if (hlsRef.current) {
+                hlsRef.current.destroy();
+                hlsRef.current = null;
+            }
+
+            const useHls = adaptive || isLossless(currentTrack.codec);
+            if (!useHls) {
+                audio.src = streamUrl;
+            } else if (Hls.isSupported()) {
+                // Adaptive playback; drop back to the plain stream if the browser can't play it
+                const hls = new Hls();
+                hls.on(Hls.Events.ERROR, (_, data) => {
+                    if (data.fatal) {
+                        hls.destroy();
+                        hlsRef.current = null;
+                        audio.src = streamUrl;
+                        if (isPlaying) {
+                            audio.play();
+                        }
+                    }
+                });
+                hls.loadSource(hlsUrl);
+                hls.attachMedia(audio);
+                hlsRef.current = hls;
+            } else if (audio.canPlayType('application/vnd.apple.mpegurl')) {
+                audio.src = hlsUrl;
+            } else {
+                audio.src = streamUrl;
+            }
+            // Reset state for new track; the API already knows how long it is
             setCurrentTime(0);
-            setDuration(0);
+            setDuration(currentTrack.duration_ms ? currentTrack.duration_ms / 1000 : 0);
             if (isPlaying) {
                 audioRef.current.play();
             }
         }
     }, [currentTrack]);
 
+    useEffect(() => {
+        return () => {
+            if (hlsRef.current) {
+                hlsRef.current.destroy();
+            }
+        };
+    }, []);
+
     useEffect(() => {
         if (isPlaying) {
             audioRef.current.play();
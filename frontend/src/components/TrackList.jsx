@@ -2,7 +2,7 @@ import React, { useState, useEffect, useRef } from 'react';
 import { usePlaylist } from '../context/PlaylistContext';
 
 const TrackList = ({ tracks, onSelect, currentTrackId, playlistId, onRemove }) => {
-    const { playlists, addTrackToPlaylist, removeTrackFromPlaylist } = usePlaylist();
+    const { playlists, addTrackToPlaylist, removeEntryFromPlaylist } = usePlaylist();
     const [openMenuTrackId, setOpenMenuTrackId] = useState(null);
 
     useEffect(() => {
@@ -23,10 +23,10 @@ const TrackList = ({ tracks, onSelect, currentTrackId, playlistId, onRemove }) =
         setOpenMenuTrackId(null);
     };
 
-    const handleRemove = async (e, trackId) => {
+    const handleRemove = async (e, entryId) => {
         e.stopPropagation();
         if (window.confirm("Remove this track from playlist?")) {
-            await removeTrackFromPlaylist(playlistId, trackId);
+            await removeEntryFromPlaylist(playlistId, entryId);
             if (onRemove) onRemove();
         }
     };
@@ -41,7 +41,7 @@ const TrackList = ({ tracks, onSelect, currentTrackId, playlistId, onRemove }) =
             <div className="space-y-2">
                 {tracks.map((track) => (
                     <div 
-                        key={track.id}
+                        key={track.entry_id ?? track.id}
                         onClick={() => onSelect(track)}
                         className={`group relative p-3 rounded-lg cursor-pointer flex items-center justify-between transition-colors ${
                             currentTrackId === track.id 
@@ -57,7 +57,7 @@ const TrackList = ({ tracks, onSelect, currentTrackId, playlistId, onRemove }) =
                         <div className="relative">
                             {playlistId ? (
                                 <button 
-                                    onClick={(e) => handleRemove(e, track.id)}
+                                    onClick={(e) => handleRemove(e, track.entry_id)}
                                     className="p-2 text-gray-400 hover:text-red-500 opacity-0 group-hover:opacity-100 transition-opacity"
                                     title="Remove from Playlist"
                                 >
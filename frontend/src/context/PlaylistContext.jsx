@@ -8,6 +8,8 @@ const PlaylistContext = createContext(null);
 export const PlaylistProvider = ({ children }) => {
     const [playlists, setPlaylists] = useState([]);
     const { user } = useAuth();
+    // Playlists are per user, so every request carries the token
+    const authConfig = () => ({ headers: { Authorization: `Bearer ${user?.token}` } });
 
     useEffect(() => {
         if (user) {
@@ -19,8 +21,17 @@ export const PlaylistProvider = ({ children }) => {
 
     const fetchPlaylists = async () => {
         try {
-            const res = await axios.get('/api/playlists');
-            setPlaylists(res.data);
+            let all = [];
+            let cursor = null;
+            do {
+                const res = await axios.get('/api/playlists', {
+                    ...authConfig(),
+                    params: cursor ? { cursor } : {},
+                });
+                all = all.concat(res.data.items);
+                cursor = res.data.next_cursor;
+            } while (cursor);
+            setPlaylists(all);
         } catch (error) {
             console.error("Failed to fetch playlists");
         }
@@ -31,7 +42,7 @@ export const PlaylistProvider = ({ children }) => {
             const res = await axios.post('/api/playlists', {
                 name,
                 description
-            });
+            }, authConfig());
             setPlaylists([res.data, ...playlists]);
             toast.success("Playlist created");
             return res.data;
@@ -40,9 +51,20 @@ export const PlaylistProvider = ({ children }) => {
             throw error;
         }
     };
+    const updatePlaylist = async (playlistId, changes) => {
+        try {
+            const res = await axios.patch(`/api/playlists/${playlistId}`, changes, authConfig());
+            setPlaylists(playlists.map(playlist => playlist.id === playlistId ? res.data : playlist));
+            toast.success("Playlist updated");
+            return res.data;
+        } catch (error) {
+            console.error(error);
+            toast.error("Failed to update playlist");
+        }
+    };
     const deletePlaylist = async(playlistId) => {
         try{
-            await axios.delete(`/api/playlists/${playlistId}`)
+            await axios.delete(`/api/playlists/${playlistId}`, authConfig())
             setPlaylists(playlists.filter(playlist => playlist.id !== playlistId));
             toast.success("Playlist deleted");
         }
@@ -56,7 +78,7 @@ export const PlaylistProvider = ({ children }) => {
         try {
             await axios.post(`/api/playlists/${playlistId}/tracks`, {
                 track_id: trackId
-            });
+            }, authConfig());
             toast.success("Track added to playlist");
         } catch (error) {
             console.error(error);
@@ -64,9 +86,9 @@ export const PlaylistProvider = ({ children }) => {
         }
     };
 
-    const removeTrackFromPlaylist = async (playlistId, trackId) => {
+    const removeEntryFromPlaylist = async (playlistId, entryId) => {
         try {
-            await axios.delete(`/api/playlists/${playlistId}/tracks/${trackId}`);
+            await axios.delete(`/api/playlists/${playlistId}/entries/${entryId}`, authConfig());
             toast.success("Track removed from playlist");
             // If we are viewing the playlist, we might want to trigger a refresh or update local state.
             // For now, let's just hope the parent component re-fetches or we can add a callback.
@@ -77,7 +99,7 @@ export const PlaylistProvider = ({ children }) => {
     };
 
     return (
-        <PlaylistContext.Provider value={{ playlists, createPlaylist, addTrackToPlaylist, removeTrackFromPlaylist, fetchPlaylists, deletePlaylist }}>
+        <PlaylistContext.Provider value={{ playlists, createPlaylist, updatePlaylist, addTrackToPlaylist, removeEntryFromPlaylist, fetchPlaylists, deletePlaylist }}>
             {children}
         </PlaylistContext.Provider>
     );
@@ -3,8 +3,9 @@ import { useParams, useNavigate } from 'react-router-dom';
 import axios from 'axios';
 import TrackList from '../components/TrackList';
 import { useOutletContext } from 'react-router-dom';
-import { MoreHorizontal, Trash2} from 'lucide-react';
+import { MoreHorizontal, Pencil, Trash2} from 'lucide-react';
 import { usePlaylist } from '../context/PlaylistContext';
+import { useAuth } from '../context/AuthContext';
 
 const Playlist = () => {
     const { id } = useParams();
@@ -14,7 +15,16 @@ const Playlist = () => {
     const [showMenu, setShowMenu] = useState(false);
     
     const { handleSelectTrack, currentTrackId } = useOutletContext() || {};
-    const { deletePlaylist } = usePlaylist();
+    const { deletePlaylist, updatePlaylist } = usePlaylist();
+    const { user } = useAuth();
+
+    const handleRename = async () => {
+        setShowMenu(false);
+        const name = window.prompt("Playlist name", playlist.name);
+        if (name === null) return;
+        const updated = await updatePlaylist(id, { name });
+        if (updated) setPlaylist({ ...playlist, ...updated });
+    };
 
     const handleDelete = async () => {
         await deletePlaylist(id);
@@ -27,7 +37,9 @@ const Playlist = () => {
 
     const fetchPlaylist = async () => {
         try {
-            const res = await axios.get(`/api/playlists/${id}`);
+            const res = await axios.get(`/api/playlists/${id}`, {
+                headers: { Authorization: `Bearer ${user?.token}` }
+            });
             setPlaylist(res.data);
             setTracks(res.data.tracks);
         } catch (error) {
@@ -49,6 +61,13 @@ const Playlist = () => {
                 
                 {showMenu && (
                     <div className="absolute right-0 top-full mt-2 w-48 bg-[#282828] rounded shadow-xl z-20 overflow-hidden border border-white/10">
+                        <button 
+                            onClick={handleRename} 
+                            className="w-full text-left px-4 py-3 text-sm text-gray-300 hover:bg-[#3E3E3E] flex items-center gap-2"
+                        >
+                            <Pencil size={16} />
+                            Rename Playlist
+                        </button>
                         <button 
                             onClick={handleDelete} 
                             className="w-full text-left px-4 py-3 text-sm text-gray-300 hover:bg-[#3E3E3E] flex items-center gap-2"
@@ -0,0 +1,12 @@
+-- Tag metadata read from the audio file during import
+ALTER TABLE tracks ALTER COLUMN title TYPE TEXT;
+ALTER TABLE tracks ALTER COLUMN artist TYPE TEXT;
+
+ALTER TABLE tracks
+    ADD COLUMN IF NOT EXISTS album TEXT,
+    ADD COLUMN IF NOT EXISTS album_artist TEXT,
+    ADD COLUMN IF NOT EXISTS track_number INTEGER,
+    ADD COLUMN IF NOT EXISTS disc_number INTEGER,
+    ADD COLUMN IF NOT EXISTS year INTEGER,
+    ADD COLUMN IF NOT EXISTS genre TEXT,
+    ADD COLUMN IF NOT EXISTS duration_ms INTEGER;
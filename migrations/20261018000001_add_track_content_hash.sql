@@ -0,0 +1,26 @@
+-- Deduplicate tracks by content instead of filename
+ALTER TABLE tracks ADD COLUMN IF NOT EXISTS content_hash TEXT;
+
+UPDATE tracks SET content_hash = encode(sha256(data), 'hex') WHERE content_hash IS NULL;
+
+-- Rows that were imported twice under different names collapse onto the oldest copy
+INSERT INTO playlist_tracks (playlist_id, track_id, added_at, order_index)
+SELECT pt.playlist_id, keep.id, pt.added_at, pt.order_index
+FROM playlist_tracks pt
+JOIN tracks dup ON dup.id = pt.track_id
+JOIN LATERAL (
+    SELECT t.id FROM tracks t
+    WHERE t.content_hash = dup.content_hash
+    ORDER BY t.created_at, t.id
+    LIMIT 1
+) keep ON keep.id <> dup.id
+ON CONFLICT DO NOTHING;
+
+DELETE FROM tracks a
+USING tracks b
+WHERE a.content_hash = b.content_hash
+  AND (a.created_at, a.id) > (b.created_at, b.id);
+
+ALTER TABLE tracks ALTER COLUMN content_hash SET NOT NULL;
+CREATE UNIQUE INDEX IF NOT EXISTS tracks_content_hash_idx ON tracks (content_hash);
+CREATE INDEX IF NOT EXISTS tracks_filename_idx ON tracks (filename);
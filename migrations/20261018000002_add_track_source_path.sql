@@ -0,0 +1,3 @@
+-- Where a track was imported from, so the library can be re-scanned
+ALTER TABLE tracks ADD COLUMN IF NOT EXISTS source_path TEXT;
+CREATE INDEX IF NOT EXISTS tracks_source_path_idx ON tracks (source_path);
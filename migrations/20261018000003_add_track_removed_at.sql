@@ -0,0 +1,2 @@
+-- Set when the source file of a track disappears from a watched library directory
+ALTER TABLE tracks ADD COLUMN IF NOT EXISTS removed_at TIMESTAMPTZ;
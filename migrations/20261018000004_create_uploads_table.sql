@@ -0,0 +1,10 @@
+-- Resumable uploads in progress; the bytes live on disk under UPLOAD_DIR
+CREATE TABLE IF NOT EXISTS uploads (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    username VARCHAR(255) NOT NULL,
+    filename TEXT NOT NULL,
+    size BIGINT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    -- When the last chunk arrived; uploads left idle past UPLOAD_TTL_HOURS are removed
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,6 @@
+-- Audio moves out of the database into a storage backend keyed by content_hash.
+-- Existing rows keep their bytes here until `arcsin migrate-storage` copies them out.
+ALTER TABLE tracks ALTER COLUMN data DROP NOT NULL;
+ALTER TABLE tracks ADD COLUMN IF NOT EXISTS size_bytes BIGINT;
+UPDATE tracks SET size_bytes = octet_length(data) WHERE size_bytes IS NULL;
+ALTER TABLE tracks ALTER COLUMN size_bytes SET NOT NULL;
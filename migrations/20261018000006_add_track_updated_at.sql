@@ -0,0 +1,3 @@
+-- Last time a track's audio or tags changed, served as Last-Modified
+ALTER TABLE tracks ADD COLUMN IF NOT EXISTS updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+UPDATE tracks SET updated_at = created_at;
@@ -0,0 +1,7 @@
+-- Probed from the audio itself rather than tags
+ALTER TABLE tracks ADD COLUMN IF NOT EXISTS codec TEXT;
+ALTER TABLE tracks ADD COLUMN IF NOT EXISTS bitrate INTEGER;
+ALTER TABLE tracks ADD COLUMN IF NOT EXISTS sample_rate INTEGER;
+ALTER TABLE tracks ADD COLUMN IF NOT EXISTS channels INTEGER;
+-- Last probe of a track imported before these existed, so unreadable ones are tried once
+ALTER TABLE tracks ADD COLUMN IF NOT EXISTS probed_at TIMESTAMPTZ;
@@ -0,0 +1,17 @@
+-- Cover art, deduplicated by content; the image itself lives in the storage backend
+CREATE TABLE IF NOT EXISTS artwork (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    content_hash TEXT NOT NULL UNIQUE,
+    mime_type TEXT NOT NULL,
+    width INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    size_bytes BIGINT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+ALTER TABLE tracks ADD COLUMN IF NOT EXISTS artwork_id UUID REFERENCES artwork(id) ON DELETE SET NULL;
+-- Finding unused artwork looks tracks up by it
+CREATE INDEX IF NOT EXISTS tracks_artwork_idx ON tracks (artwork_id);
+
+-- Tracks imported before artwork was extracted get probed for it on next start
+ALTER TABLE tracks ADD COLUMN IF NOT EXISTS artwork_probed BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,5 @@
+-- EBU R128 loudness and ReplayGain 2.0 style gains (reference -18 LUFS)
+ALTER TABLE tracks ADD COLUMN IF NOT EXISTS loudness_lufs REAL;
+ALTER TABLE tracks ADD COLUMN IF NOT EXISTS true_peak_dbtp REAL;
+ALTER TABLE tracks ADD COLUMN IF NOT EXISTS track_gain_db REAL;
+ALTER TABLE tracks ADD COLUMN IF NOT EXISTS album_gain_db REAL;
@@ -0,0 +1,34 @@
+-- Artists and albums as entities; names are matched on a normalized form so that
+-- variants ("Beyoncé", "beyonce") land on the same row
+CREATE TABLE IF NOT EXISTS artists (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    name TEXT NOT NULL,
+    normalized_name TEXT NOT NULL UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS albums (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    title TEXT NOT NULL,
+    normalized_title TEXT NOT NULL,
+    artist_id UUID REFERENCES artists(id) ON DELETE SET NULL,
+    year INTEGER,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+-- One album per title and artist, artistless ones included. NULLS NOT DISTINCT would
+-- say this directly but needs Postgres 15
+CREATE UNIQUE INDEX IF NOT EXISTS albums_title_artist_key
+    ON albums (normalized_title, COALESCE(artist_id, '00000000-0000-0000-0000-000000000000'));
+
+-- Track credits; role is 'main' or 'featured', position keeps the tag's order
+CREATE TABLE IF NOT EXISTS track_artists (
+    track_id UUID NOT NULL REFERENCES tracks(id) ON DELETE CASCADE,
+    artist_id UUID NOT NULL REFERENCES artists(id) ON DELETE CASCADE,
+    role TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    PRIMARY KEY (track_id, artist_id)
+);
+CREATE INDEX IF NOT EXISTS track_artists_artist_idx ON track_artists (artist_id);
+
+ALTER TABLE tracks ADD COLUMN IF NOT EXISTS album_id UUID REFERENCES albums(id) ON DELETE SET NULL;
+CREATE INDEX IF NOT EXISTS tracks_album_idx ON tracks (album_id);
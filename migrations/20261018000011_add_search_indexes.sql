@@ -0,0 +1,48 @@
+-- Full-text and fuzzy search over tracks, artists, albums and playlists
+CREATE EXTENSION IF NOT EXISTS unaccent;
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+-- unaccent() is only STABLE, which keeps it out of indexes; the dictionary never changes
+CREATE OR REPLACE FUNCTION f_unaccent(TEXT) RETURNS TEXT
+    LANGUAGE sql IMMUTABLE PARALLEL SAFE STRICT
+    AS $$ SELECT public.unaccent('public.unaccent'::regdictionary, $1) $$;
+
+-- No stemming (names and titles aren't prose), but accents are folded
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM pg_ts_config WHERE cfgname = 'arcsin_search') THEN
+        CREATE TEXT SEARCH CONFIGURATION arcsin_search (COPY = simple);
+        ALTER TEXT SEARCH CONFIGURATION arcsin_search
+            ALTER MAPPING FOR hword, hword_part, word WITH unaccent, simple;
+    END IF;
+END
+$$;
+
+ALTER TABLE tracks ADD COLUMN IF NOT EXISTS search_vector TSVECTOR GENERATED ALWAYS AS (
+    setweight(to_tsvector('arcsin_search', coalesce(title, '')), 'A') ||
+    setweight(to_tsvector('arcsin_search', coalesce(artist, '')), 'B') ||
+    setweight(to_tsvector('arcsin_search', coalesce(album, '')), 'C') ||
+    setweight(to_tsvector('arcsin_search', coalesce(genre, '')), 'D')
+) STORED;
+CREATE INDEX IF NOT EXISTS tracks_search_idx ON tracks USING GIN (search_vector);
+CREATE INDEX IF NOT EXISTS tracks_title_trgm_idx ON tracks USING GIN (f_unaccent(title) gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS tracks_artist_trgm_idx ON tracks USING GIN (f_unaccent(artist) gin_trgm_ops);
+
+ALTER TABLE artists ADD COLUMN IF NOT EXISTS search_vector TSVECTOR GENERATED ALWAYS AS (
+    to_tsvector('arcsin_search', name)
+) STORED;
+CREATE INDEX IF NOT EXISTS artists_search_idx ON artists USING GIN (search_vector);
+CREATE INDEX IF NOT EXISTS artists_name_trgm_idx ON artists USING GIN (f_unaccent(name) gin_trgm_ops);
+
+ALTER TABLE albums ADD COLUMN IF NOT EXISTS search_vector TSVECTOR GENERATED ALWAYS AS (
+    to_tsvector('arcsin_search', title)
+) STORED;
+CREATE INDEX IF NOT EXISTS albums_search_idx ON albums USING GIN (search_vector);
+CREATE INDEX IF NOT EXISTS albums_title_trgm_idx ON albums USING GIN (f_unaccent(title) gin_trgm_ops);
+
+ALTER TABLE playlists ADD COLUMN IF NOT EXISTS search_vector TSVECTOR GENERATED ALWAYS AS (
+    setweight(to_tsvector('arcsin_search', name), 'A') ||
+    setweight(to_tsvector('arcsin_search', coalesce(description, '')), 'B')
+) STORED;
+CREATE INDEX IF NOT EXISTS playlists_search_idx ON playlists USING GIN (search_vector);
+CREATE INDEX IF NOT EXISTS playlists_name_trgm_idx ON playlists USING GIN (f_unaccent(name) gin_trgm_ops);
@@ -0,0 +1,4 @@
+-- Playlists belong to the user who made them. Older playlists have no owner.
+ALTER TABLE playlists
+    ADD CONSTRAINT playlists_user_id_fkey FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE;
+CREATE INDEX IF NOT EXISTS playlists_user_id_idx ON playlists (user_id, created_at);
@@ -0,0 +1,14 @@
+-- Playlist positions are spaced out so a move only has to rewrite the moved track
+ALTER TABLE playlist_tracks ALTER COLUMN order_index TYPE BIGINT;
+
+UPDATE playlist_tracks pt
+SET order_index = ranked.position * 1024
+FROM (
+    SELECT playlist_id, track_id,
+           row_number() OVER (PARTITION BY playlist_id ORDER BY order_index, added_at, track_id) - 1
+               AS position
+    FROM playlist_tracks
+) ranked
+WHERE ranked.playlist_id = pt.playlist_id AND ranked.track_id = pt.track_id;
+
+CREATE INDEX IF NOT EXISTS playlist_tracks_order_idx ON playlist_tracks (playlist_id, order_index);
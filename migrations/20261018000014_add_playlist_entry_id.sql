@@ -0,0 +1,10 @@
+-- Playlist entries get their own id, so the same track can be in a playlist twice
+ALTER TABLE playlist_tracks ADD COLUMN IF NOT EXISTS id UUID NOT NULL DEFAULT gen_random_uuid();
+
+ALTER TABLE playlist_tracks DROP CONSTRAINT IF EXISTS playlist_tracks_pkey;
+ALTER TABLE playlist_tracks ADD PRIMARY KEY (id);
+
+-- added_at used to be nullable; SET NOT NULL fails while any row lacks one
+UPDATE playlist_tracks SET added_at = NOW() WHERE added_at IS NULL;
+ALTER TABLE playlist_tracks ALTER COLUMN added_at SET NOT NULL;
+CREATE INDEX IF NOT EXISTS playlist_tracks_track_id_idx ON playlist_tracks (track_id);
@@ -0,0 +1,3 @@
+-- Last time a playlist's details or entries changed
+ALTER TABLE playlists ADD COLUMN IF NOT EXISTS updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+UPDATE playlists SET updated_at = COALESCE(created_at, updated_at);
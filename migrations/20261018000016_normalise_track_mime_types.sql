@@ -0,0 +1,3 @@
+-- Sniffed uploads and imports stored infer's legacy MIME names
+UPDATE tracks SET mime_type = 'audio/flac' WHERE mime_type = 'audio/x-flac';
+UPDATE tracks SET mime_type = 'audio/mp4' WHERE mime_type = 'audio/m4a';
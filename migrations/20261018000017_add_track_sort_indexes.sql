@@ -0,0 +1,5 @@
+-- Keyset pages seek on (sort key, id); the expressions match TrackSort::expr exactly
+CREATE INDEX IF NOT EXISTS tracks_title_id_idx ON tracks (title, id) WHERE removed_at IS NULL;
+CREATE INDEX IF NOT EXISTS tracks_artist_id_idx ON tracks ((COALESCE(artist, '')), id) WHERE removed_at IS NULL;
+CREATE INDEX IF NOT EXISTS tracks_duration_id_idx ON tracks ((COALESCE(duration_ms, 0)), id) WHERE removed_at IS NULL;
+CREATE INDEX IF NOT EXISTS tracks_created_at_id_idx ON tracks (created_at, id) WHERE removed_at IS NULL;
@@ -1,5 +1,6 @@
 use std::{sync::{mpsc, Arc, Mutex}, thread};
 
+#[allow(dead_code)]
 pub struct ThreadPool{
     workers: Vec<Worker>, // vector of threads waiting to pick up code
     sender: mpsc::Sender<Job>, // sender for message passing
@@ -31,6 +32,7 @@ impl ThreadPool{
     }
 }
 
+#[allow(dead_code)]
 pub struct Worker{
     id: usize,
     thread: thread::JoinHandle<()>,// thread handle
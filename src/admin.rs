@@ -0,0 +1,24 @@
+use axum::{extract::State, http::StatusCode, Extension, Json};
+use std::sync::Arc;
+
+use crate::auth::Claims;
+use crate::transcode_cache::CacheStats;
+use crate::AppState;
+
+/// Users listed in `ADMIN_USERS` (comma separated usernames).
+fn is_admin(claims: &Claims) -> bool {
+    std::env::var("ADMIN_USERS")
+        .unwrap_or_default()
+        .split(',')
+        .any(|user| user.trim() == claims.sub)
+}
+
+pub async fn transcode_cache_stats(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Json<CacheStats>, StatusCode> {
+    if !is_admin(&claims) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(Json(state.app.transcodes.stats()))
+}
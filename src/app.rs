@@ -1,76 +1,736 @@
-use crate::models::TrackRecord;
-use sqlx::PgPool;
+use crate::artwork;
+use crate::catalog;
+use crate::loudness::{self, Loudness};
+use crate::metadata::TrackMetadata;
+use crate::models::{TrackListQuery, TrackRecord};
+use crate::page::{self, Cursor, Page};
+use crate::scan::{self, ScanFailure, ScanOptions};
+use crate::storage::{self, Storage};
+use crate::transcode::Encoder;
+use crate::transcode_cache::TranscodeCache;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::{PgPool, Postgres, QueryBuilder};
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use uuid::Uuid;
 
 pub struct App {
     pub db: PgPool,
+    pub storage: Arc<dyn Storage>,
+    pub transcodes: TranscodeCache,
+    /// For lossy transcodes; without it they fall back to the original.
+    pub encoder: Option<Encoder>,
 }
 
-impl App {
-    pub fn new(db: PgPool) -> Self {
-        Self { db }
-    }
-
-    pub async fn import_tracks_from_dir(&self, path: &Path) -> std::io::Result<()> {
-        let entries = fs::read_dir(path)?;
-        for entry in entries.flatten() {
-            let path = entry.path();
-            if path.is_file() {
-                if let Some(extension) = path.extension() {
-                    let ext = extension.to_string_lossy().to_lowercase();
-                    if ["mp3", "wav", "ogg", "flac"].contains(&ext.as_str()) {
-                        self.import_track(&path).await.ok();
+/// What happens to a source file once it has been imported (or found to be a duplicate).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ImportPolicy {
+    /// Leave the file where it is.
+    #[default]
+    Keep,
+    /// Move the file into the given directory.
+    Archive(PathBuf),
+    /// Remove the file from disk.
+    Delete,
+}
+
+impl ImportPolicy {
+    /// Reads `IMPORT_POLICY` (`keep`, `archive` or `delete`) and `IMPORT_ARCHIVE_DIR`.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let policy = std::env::var("IMPORT_POLICY").unwrap_or_default();
+        match policy.to_lowercase().as_str() {
+            "" | "keep" => Ok(Self::Keep),
+            "delete" => Ok(Self::Delete),
+            "archive" => {
+                let dir = std::env::var("IMPORT_ARCHIVE_DIR").map_err(|_| {
+                    anyhow::anyhow!("IMPORT_ARCHIVE_DIR must be set when IMPORT_POLICY=archive")
+                })?;
+                Ok(Self::Archive(PathBuf::from(dir)))
+            }
+            other => anyhow::bail!("Unknown IMPORT_POLICY: {}", other),
+        }
+    }
+
+    fn apply(&self, path: &Path, content_hash: &str) -> std::io::Result<()> {
+        match self {
+            Self::Keep => Ok(()),
+            Self::Delete => {
+                fs::remove_file(path)?;
+                println!("Deleted file: {:?}", path);
+                Ok(())
+            }
+            Self::Archive(dir) => {
+                fs::create_dir_all(dir)?;
+                let filename = path.file_name().unwrap_or_default();
+                let mut target = dir.join(filename);
+                if target.exists() {
+                    // Never overwrite something already in the archive
+                    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+                    let mut name = format!("{}-{}", stem, &content_hash[..8]);
+                    if let Some(ext) = path.extension() {
+                        name = format!("{}.{}", name, ext.to_string_lossy());
                     }
+                    target = dir.join(name);
                 }
+                // rename fails across filesystems, fall back to copy + remove
+                if fs::rename(path, &target).is_err() {
+                    fs::copy(path, &target)?;
+                    fs::remove_file(path)?;
+                }
+                println!("Archived file: {:?} -> {:?}", path, target);
+                Ok(())
             }
         }
-        Ok(())
     }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportStatus {
+    /// Content not seen before, stored as a new track.
+    New,
+    /// Identical bytes already exist; nothing was stored.
+    DuplicateContent,
+    /// Stored as a new track, but another track already uses the same filename.
+    FilenameClash,
+    /// The file at a known source path changed; its track was updated in place.
+    Updated,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportResult {
+    pub path: PathBuf,
+    pub status: ImportStatus,
+    pub track_id: Uuid,
+}
 
-    async fn import_track(&self, path: &PathBuf) -> anyhow::Result<()> {
+#[derive(Debug, Default, Serialize)]
+pub struct ImportReport {
+    pub results: Vec<ImportResult>,
+    pub failures: Vec<ScanFailure>,
+}
+
+impl ImportReport {
+    pub fn count(&self, status: ImportStatus) -> usize {
+        self.results.iter().filter(|r| r.status == status).count()
+    }
+}
+
+impl App {
+    pub fn new(db: PgPool, storage: Arc<dyn Storage>, transcodes: TranscodeCache) -> Self {
+        Self {
+            db,
+            storage,
+            transcodes,
+            encoder: Encoder::from_env(),
+        }
+    }
+
+    pub async fn import_tracks_from_dir(
+        &self,
+        path: &Path,
+        options: &ScanOptions,
+        policy: &ImportPolicy,
+    ) -> anyhow::Result<ImportReport> {
+        self.import_tracks_within(path, path, options, policy).await
+    }
+
+    /// Imports what a scan of the library at `root` would find inside `dir`.
+    pub async fn import_tracks_within(
+        &self,
+        root: &Path,
+        dir: &Path,
+        options: &ScanOptions,
+        policy: &ImportPolicy,
+    ) -> anyhow::Result<ImportReport> {
+        let (mut files, failures) = options.collect_within(root, dir)?;
+        let mut report = ImportReport {
+            failures,
+            ..Default::default()
+        };
+
+        // Don't pick up files we archived ourselves on an earlier run
+        if let ImportPolicy::Archive(dir) = policy {
+            if let Ok(archive) = fs::canonicalize(dir) {
+                files.retain(|f| !fs::canonicalize(f).is_ok_and(|f| f.starts_with(&archive)));
+            }
+        }
+
+        for path in files {
+            match self.import_track(&path, policy).await {
+                Ok(result) => report.results.push(result),
+                Err(e) => {
+                    report.failures.push(ScanFailure {
+                        path,
+                        error: e.to_string(),
+                    });
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    pub async fn import_track(
+        &self,
+        path: &Path,
+        policy: &ImportPolicy,
+    ) -> anyhow::Result<ImportResult> {
         let filename = path.file_name().unwrap().to_string_lossy().to_string();
+        // Nothing to re-scan once the file is gone
+        let source_path = match policy {
+            ImportPolicy::Delete => None,
+            _ => Some(fs::canonicalize(path)?.to_string_lossy().to_string()),
+        };
+        let data = fs::read(path)?;
+        let content_hash = format!("{:x}", Sha256::digest(&data));
+
+        // Same bytes already in the library; just remember where we saw them
+        let existing = sqlx::query!(
+            r#"
+            UPDATE tracks
+            SET source_path = CASE
+                    WHEN source_path IS NULL OR removed_at IS NOT NULL THEN $2
+                    ELSE source_path
+                END,
+                removed_at = NULL
+            WHERE content_hash = $1
+            RETURNING id
+            "#,
+            content_hash,
+            source_path
+        )
+        .fetch_optional(&self.db)
+        .await?;
+
+        if let Some(existing) = existing {
+            println!("Skipped duplicate: {}", filename);
+            // Heal rows whose blob went missing from storage
+            if !self.storage.exists(&content_hash).await? {
+                self.storage.put(&content_hash, data.into()).await?;
+            }
+            policy.apply(path, &content_hash)?;
+            return Ok(ImportResult {
+                path: path.to_path_buf(),
+                status: ImportStatus::DuplicateContent,
+                track_id: existing.id,
+            });
+        }
+
+        let mime_type = match scan::sniff_audio(&data) {
+            Some(mime) => mime.to_string(),
+            None => mime_guess::from_path(path)
+                .first_or_octet_stream()
+                .to_string(),
+        };
+        let mut meta = TrackMetadata::read(path);
+        let loudness = measure_loudness(data.clone(), &mime_type, &filename).await;
+        let title = meta.title.clone().unwrap_or_else(|| filename.clone());
+        let size_bytes = data.len() as i64;
+
+        // Blob first, so a row never points at audio that isn't stored yet
+        self.storage.put(&content_hash, data.into()).await?;
+        let artwork_id = artwork::for_track(self, meta.artwork.take(), path.parent()).await;
+
+        // A file we imported before has new content, keep the track id so playlists survive
+        let updated = sqlx::query!(
+            r#"
+            WITH old AS (
+                SELECT id, content_hash FROM tracks
+                WHERE source_path = $1
+                ORDER BY created_at
+                LIMIT 1
+            )
+            UPDATE tracks t
+            SET title = $2, artist = $3, album = $4, album_artist = $5, track_number = $6,
+                disc_number = $7, year = $8, genre = $9, duration_ms = $10, filename = $11,
+                data = NULL, size_bytes = $12, mime_type = $13, content_hash = $14,
+                codec = $15, bitrate = $16, sample_rate = $17, channels = $18, artwork_id = $19,
+                artwork_probed = TRUE,
+                loudness_lufs = $20, true_peak_dbtp = $21, track_gain_db = $22,
+                removed_at = NULL, updated_at = NOW()
+            FROM old
+            WHERE t.id = old.id
+            RETURNING t.id, old.content_hash AS "old_hash!"
+            "#,
+            source_path,
+            title,
+            meta.artist,
+            meta.album,
+            meta.album_artist,
+            meta.track_number,
+            meta.disc_number,
+            meta.year,
+            meta.genre,
+            meta.duration_ms,
+            filename,
+            size_bytes,
+            mime_type,
+            content_hash,
+            meta.codec,
+            meta.bitrate,
+            meta.sample_rate,
+            meta.channels,
+            artwork_id,
+            loudness.map(|l| l.integrated as f32),
+            loudness.map(|l| l.true_peak as f32),
+            loudness.map(|l| l.track_gain() as f32)
+        )
+        .fetch_optional(&self.db)
+        .await;
+
+        let updated = match updated {
+            // The new content is another track's: a concurrent import got there first
+            Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+                let row =
+                    sqlx::query!("SELECT id FROM tracks WHERE content_hash = $1", content_hash)
+                        .fetch_one(&self.db)
+                        .await?;
+                println!("Skipped duplicate: {}", filename);
+                policy.apply(path, &content_hash)?;
+                return Ok(ImportResult {
+                    path: path.to_path_buf(),
+                    status: ImportStatus::DuplicateContent,
+                    track_id: row.id,
+                });
+            }
+            updated => updated?,
+        };
+
+        if let Some(updated) = updated {
+            println!("Updated track: {}", filename);
+            // content_hash is unique, so nothing else can reference the old blob
+            self.storage.delete(&updated.old_hash).await?;
+            catalog::link_track(self, updated.id, &title, &meta).await?;
+            self.update_album_gains(meta.album.as_deref()).await?;
+            policy.apply(path, &content_hash)?;
+            return Ok(ImportResult {
+                path: path.to_path_buf(),
+                status: ImportStatus::Updated,
+                track_id: updated.id,
+            });
+        }
 
-        // Check if exists by filename (simple deduplication)
-        let exists = sqlx::query!("SELECT id FROM tracks WHERE filename = $1", filename)
+        let clash = sqlx::query!("SELECT id FROM tracks WHERE filename = $1 LIMIT 1", filename)
             .fetch_optional(&self.db)
             .await?;
 
-        if exists.is_none() {
-            println!("Importing track: {}", filename);
-            let data = fs::read(path)?;
-            let mime_type = mime_guess::from_path(path)
-                .first_or_octet_stream()
-                .to_string();
-
-            sqlx::query!(
-                "INSERT INTO tracks (title, filename, data, mime_type) VALUES ($1, $2, $3, $4)",
-                filename, // Use filename as title for now
-                filename,
-                data,
-                mime_type
+        println!("Importing track: {}", filename);
+
+        let inserted = sqlx::query!(
+            r#"
+            INSERT INTO tracks (
+                title, artist, album, album_artist, track_number, disc_number,
+                year, genre, duration_ms, filename, size_bytes, mime_type, content_hash,
+                source_path, codec, bitrate, sample_rate, channels, artwork_id, artwork_probed,
+                loudness_lufs, true_peak_dbtp, track_gain_db
+            )
+            VALUES (
+                $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18,
+                $19, TRUE, $20, $21, $22
             )
-            .execute(&self.db)
+            ON CONFLICT (content_hash) DO NOTHING
+            RETURNING id
+            "#,
+            title,
+            meta.artist,
+            meta.album,
+            meta.album_artist,
+            meta.track_number,
+            meta.disc_number,
+            meta.year,
+            meta.genre,
+            meta.duration_ms,
+            filename,
+            size_bytes,
+            mime_type,
+            content_hash,
+            source_path,
+            meta.codec,
+            meta.bitrate,
+            meta.sample_rate,
+            meta.channels,
+            artwork_id,
+            loudness.map(|l| l.integrated as f32),
+            loudness.map(|l| l.true_peak as f32),
+            loudness.map(|l| l.track_gain() as f32)
+        )
+        .fetch_optional(&self.db)
+        .await?;
+
+        let (status, track_id) = match inserted {
+            Some(row) if clash.is_some() => (ImportStatus::FilenameClash, row.id),
+            Some(row) => (ImportStatus::New, row.id),
+            None => {
+                // Lost a race against a concurrent import of the same content
+                let row =
+                    sqlx::query!("SELECT id FROM tracks WHERE content_hash = $1", content_hash)
+                        .fetch_one(&self.db)
+                        .await?;
+                (ImportStatus::DuplicateContent, row.id)
+            }
+        };
+
+        if status != ImportStatus::DuplicateContent {
+            catalog::link_track(self, track_id, &title, &meta).await?;
+        }
+        self.update_album_gains(meta.album.as_deref()).await?;
+        policy.apply(path, &content_hash)?;
+
+        Ok(ImportResult {
+            path: path.to_path_buf(),
+            status,
+            track_id,
+        })
+    }
+
+    /// Flags tracks whose source file (or a directory containing it) was removed.
+    pub async fn mark_removed(&self, path: &Path) -> anyhow::Result<u64> {
+        let path = path.to_string_lossy().to_string();
+        let dir_prefix = format!("{}/", path.trim_end_matches('/'));
+        let result = sqlx::query!(
+            r#"
+            UPDATE tracks SET removed_at = NOW()
+            WHERE removed_at IS NULL
+              AND (source_path = $1 OR left(source_path, length($2)) = $2)
+            "#,
+            path,
+            dir_prefix
+        )
+        .execute(&self.db)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Follows a rename inside a watched directory without re-importing anything.
+    pub async fn rename_source(&self, from: &Path, to: &Path) -> anyhow::Result<u64> {
+        let from = from.to_string_lossy().to_string();
+        let to = to.to_string_lossy().to_string();
+        let result = sqlx::query!(
+            r#"
+            UPDATE tracks
+            SET source_path = $2 || substr(source_path, length($1) + 1), removed_at = NULL
+            WHERE source_path = $1 OR left(source_path, length($1) + 1) = $1 || '/'
+            "#,
+            from,
+            to
+        )
+        .execute(&self.db)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Copies audio still held in `tracks.data` out to the storage backend, a batch at a time.
+    pub async fn migrate_data_to_storage(&self) -> anyhow::Result<u64> {
+        let mut moved = 0;
+        loop {
+            let rows = sqlx::query!(
+                r#"
+                SELECT id, content_hash, data AS "data!"
+                FROM tracks
+                WHERE data IS NOT NULL
+                LIMIT 10
+                "#
+            )
+            .fetch_all(&self.db)
+            .await?;
+
+            if rows.is_empty() {
+                return Ok(moved);
+            }
+
+            for row in rows {
+                if !self.storage.exists(&row.content_hash).await? {
+                    self.storage.put(&row.content_hash, row.data.into()).await?;
+                }
+                sqlx::query!("UPDATE tracks SET data = NULL WHERE id = $1", row.id)
+                    .execute(&self.db)
+                    .await?;
+                moved += 1;
+                println!("Moved track {} ({})", row.id, row.content_hash);
+            }
+        }
+    }
+
+    /// Probes audio properties and cover art for tracks imported before they were
+    /// recorded. Art comes from the tags, or a sidecar image next to the source file.
+    pub async fn probe_missing_properties(&self) -> anyhow::Result<u64> {
+        let mut probed = 0;
+        let mut after = Uuid::nil();
+        loop {
+            // Failures are recorded, so tracks that can't be decoded are tried once
+            let rows = sqlx::query!(
+                r#"
+                SELECT id, content_hash, mime_type, size_bytes, source_path
+                FROM tracks
+                WHERE ((codec IS NULL AND probed_at IS NULL) OR NOT artwork_probed)
+                  AND data IS NULL AND id > $1
+                ORDER BY id
+                LIMIT 10
+                "#,
+                after
+            )
+            .fetch_all(&self.db)
             .await?;
 
-            // Delete file after successful import
-            fs::remove_file(path)?;
-            println!("Deleted file: {}", filename);
-        } else {
-            // File already exists in database, delete it from assets
-            fs::remove_file(path)?;
-            println!("Skipped duplicate and deleted file: {}", filename);
+            let Some(last) = rows.last() else {
+                return Ok(probed);
+            };
+            after = last.id;
+
+            for row in rows {
+                let data = storage::read_all(
+                    self.storage.as_ref(),
+                    &row.content_hash,
+                    row.size_bytes as u64,
+                )
+                .await?;
+                let mime_type = row.mime_type.clone();
+                let mut meta = match tokio::task::spawn_blocking(move || {
+                    TrackMetadata::read_bytes(data, &mime_type)
+                })
+                .await?
+                {
+                    Ok(meta) => meta,
+                    Err(e) => {
+                        eprintln!("Failed to probe track {}: {}", row.id, e);
+                        sqlx::query!(
+                            "UPDATE tracks SET probed_at = NOW(), artwork_probed = TRUE WHERE id = $1",
+                            row.id
+                        )
+                        .execute(&self.db)
+                        .await?;
+                        continue;
+                    }
+                };
+                let dir = row
+                    .source_path
+                    .as_deref()
+                    .and_then(|p| Path::new(p).parent());
+                let artwork_id = artwork::for_track(self, meta.artwork.take(), dir).await;
+
+                sqlx::query!(
+                    r#"
+                    UPDATE tracks
+                    SET duration_ms = COALESCE($2, duration_ms), codec = $3, bitrate = $4,
+                        sample_rate = $5, channels = $6, artwork_id = COALESCE($7, artwork_id),
+                        artwork_probed = TRUE, probed_at = NOW()
+                    WHERE id = $1
+                    "#,
+                    row.id,
+                    meta.duration_ms,
+                    meta.codec,
+                    meta.bitrate,
+                    meta.sample_rate,
+                    meta.channels,
+                    artwork_id
+                )
+                .execute(&self.db)
+                .await?;
+                probed += 1;
+            }
         }
-        Ok(())
     }
 
-    pub async fn get_tracks(&self) -> anyhow::Result<Vec<TrackRecord>> {
-        let tracks = sqlx::query_as!(
-            TrackRecord,
-            "SELECT id, title, artist, filename, mime_type, created_at, ''::bytea as \"data!\" FROM tracks"
+    /// Links tracks imported before artists and albums existed to them.
+    pub async fn link_missing_catalog(&self) -> anyhow::Result<u64> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT id, title, artist, album, album_artist, year
+            FROM tracks t
+            WHERE (artist IS NOT NULL
+                   AND NOT EXISTS (SELECT 1 FROM track_artists ta WHERE ta.track_id = t.id))
+               OR (album IS NOT NULL AND album_id IS NULL)
+            "#
         )
         .fetch_all(&self.db)
         .await?;
-        Ok(tracks)
+
+        let mut linked = 0;
+        for row in rows {
+            let meta = TrackMetadata {
+                artist: row.artist,
+                album: row.album,
+                album_artist: row.album_artist,
+                year: row.year,
+                ..Default::default()
+            };
+            catalog::link_track(self, row.id, &row.title, &meta).await?;
+            linked += 1;
+        }
+        Ok(linked)
+    }
+
+    /// Measures loudness for tracks imported before it was recorded, then brings every
+    /// album gain up to date.
+    pub async fn analyze_missing_loudness(&self) -> anyhow::Result<u64> {
+        let mut analyzed = 0;
+        let mut after = Uuid::nil();
+        loop {
+            // Tracks the prober already failed to decode would only fail again
+            let rows = sqlx::query!(
+                r#"
+                SELECT id, content_hash, mime_type, size_bytes
+                FROM tracks
+                WHERE loudness_lufs IS NULL AND data IS NULL AND id > $1
+                  AND NOT (codec IS NULL AND probed_at IS NOT NULL)
+                ORDER BY id
+                LIMIT 10
+                "#,
+                after
+            )
+            .fetch_all(&self.db)
+            .await?;
+
+            let Some(last) = rows.last() else {
+                break;
+            };
+            after = last.id;
+
+            for row in rows {
+                let data = storage::read_all(
+                    self.storage.as_ref(),
+                    &row.content_hash,
+                    row.size_bytes as u64,
+                )
+                .await?;
+                let Some(loudness) =
+                    measure_loudness(data, &row.mime_type, &row.id.to_string()).await
+                else {
+                    continue;
+                };
+
+                sqlx::query!(
+                    r#"
+                    UPDATE tracks
+                    SET loudness_lufs = $2, true_peak_dbtp = $3, track_gain_db = $4
+                    WHERE id = $1
+                    "#,
+                    row.id,
+                    loudness.integrated as f32,
+                    loudness.true_peak as f32,
+                    loudness.track_gain() as f32
+                )
+                .execute(&self.db)
+                .await?;
+                analyzed += 1;
+            }
+        }
+
+        if analyzed > 0 {
+            self.update_album_gains(None).await?;
+        }
+        Ok(analyzed)
+    }
+
+    /// Recomputes album gain for `album`, or for every album. An album's loudness is the
+    /// duration-weighted mean of its tracks' in the energy domain, which is close to
+    /// gating the whole album at once without keeping every track's gating blocks.
+    pub async fn update_album_gains(&self, album: Option<&str>) -> anyhow::Result<()> {
+        sqlx::query!(
+            r#"
+            UPDATE tracks t
+            SET album_gain_db = a.gain
+            FROM (
+                SELECT album, COALESCE(album_artist, artist) AS artist,
+                       $2 - 10 * log(
+                           SUM(duration_ms * power(10, loudness_lufs / 10.0))
+                           / NULLIF(SUM(duration_ms), 0)
+                       ) AS gain
+                FROM tracks
+                WHERE album IS NOT NULL AND loudness_lufs IS NOT NULL
+                  AND duration_ms IS NOT NULL AND removed_at IS NULL
+                  AND ($1::TEXT IS NULL OR album = $1)
+                GROUP BY 1, 2
+            ) a
+            WHERE t.album = a.album
+              AND COALESCE(t.album_artist, t.artist) IS NOT DISTINCT FROM a.artist
+            "#,
+            album,
+            loudness::REFERENCE_LUFS
+        )
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    /// One page of tracks, filtered and sorted as asked.
+    pub async fn get_tracks(
+        &self,
+        query: &TrackListQuery,
+        cursor: Option<&Cursor>,
+        limit: i64,
+    ) -> anyhow::Result<Page<TrackRecord>> {
+        let mut count = QueryBuilder::new("SELECT COUNT(*) FROM tracks WHERE removed_at IS NULL");
+        push_track_filters(&mut count, query);
+        let total: i64 = count.build_query_scalar().fetch_one(&self.db).await?;
+
+        let sort = query.sort;
+        let mut select = QueryBuilder::new(format!(
+            "SELECT {} FROM tracks WHERE removed_at IS NULL",
+            TrackRecord::columns("tracks")
+        ));
+        push_track_filters(&mut select, query);
+        if let Some(cursor) = cursor {
+            cursor.push_after(&mut select, sort.expr(), "id");
+        }
+        let order = query.order.sql();
+        select
+            .push(format!(
+                " ORDER BY {} {}, id {} LIMIT ",
+                sort.expr(),
+                order,
+                order
+            ))
+            .push_bind(limit + 1);
+        let tracks = select
+            .build_query_as::<TrackRecord>()
+            .fetch_all(&self.db)
+            .await?;
+
+        Ok(page::paginate(tracks, limit, total, |track| {
+            sort.cursor(query.order, track)
+        }))
+    }
+}
+
+fn push_track_filters<'a>(builder: &mut QueryBuilder<'a, Postgres>, query: &'a TrackListQuery) {
+    let text_filters = [
+        ("artist", &query.artist),
+        ("album", &query.album),
+        ("genre", &query.genre),
+        ("mime_type", &query.mime_type),
+    ];
+    for (column, value) in text_filters {
+        if let Some(value) = value {
+            builder
+                .push(format!(" AND lower({}) = lower(", column))
+                .push_bind(value)
+                .push(")");
+        }
+    }
+    if let Some(from) = query.year_from {
+        builder.push(" AND year >= ").push_bind(from);
+    }
+    if let Some(to) = query.year_to {
+        builder.push(" AND year <= ").push_bind(to);
+    }
+}
+
+/// Measures loudness on the blocking pool. A track that can't be measured is logged and
+/// imported anyway, without gains.
+async fn measure_loudness(data: Vec<u8>, mime_type: &str, name: &str) -> Option<Loudness> {
+    let mime_type = mime_type.to_string();
+    let result = tokio::task::spawn_blocking(move || loudness::analyze(data, &mime_type))
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|result| result);
+    match result {
+        Ok(loudness) => Some(loudness),
+        Err(e) => {
+            eprintln!("Failed to measure loudness of {}: {}", name, e);
+            None
+        }
     }
 }
@@ -0,0 +1,249 @@
+//! Cover art: extraction at import time and the resizing `/api/artwork/:id` endpoint.
+
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
+use image::{ImageFormat, ImageReader};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::io::Cursor;
+use std::path::{Path as FsPath, PathBuf};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::app::App;
+use crate::cache;
+use crate::storage;
+use crate::transcode;
+use crate::transcode_cache::TranscodeCache;
+use crate::AppState;
+
+/// Sidecar images looked for next to a track, in order of preference.
+const SIDECAR_STEMS: &[&str] = &["cover", "folder", "front", "album"];
+const SIDECAR_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "webp"];
+const MIN_SIZE: u32 = 16;
+const MAX_SIZE: u32 = 2048;
+const JPEG_QUALITY: u8 = 85;
+
+/// `cover.jpg`, `folder.png` and friends in `dir`, matched case-insensitively.
+pub fn find_sidecar(dir: &FsPath) -> Option<PathBuf> {
+    let mut found: Vec<(usize, PathBuf)> = std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let path = entry.path();
+            let stem = path.file_stem()?.to_str()?.to_lowercase();
+            let ext = path.extension()?.to_str()?.to_lowercase();
+            let rank = SIDECAR_STEMS.iter().position(|s| *s == stem)?;
+            SIDECAR_EXTENSIONS
+                .contains(&ext.as_str())
+                .then_some((rank, path))
+        })
+        .collect();
+    found.sort();
+    found.into_iter().next().map(|(_, path)| path)
+}
+
+/// Stores an image (once per distinct content) and returns its artwork id.
+pub async fn store(app: &App, data: Vec<u8>) -> anyhow::Result<Uuid> {
+    let reader = ImageReader::new(Cursor::new(&data)).with_guessed_format()?;
+    let format = reader
+        .format()
+        .ok_or_else(|| anyhow::anyhow!("Unrecognised image format"))?;
+    let (width, height) = reader.into_dimensions()?;
+    let content_hash = format!("{:x}", Sha256::digest(&data));
+    let size_bytes = data.len() as i64;
+
+    if !app.storage.exists(&content_hash).await? {
+        app.storage.put(&content_hash, data.into()).await?;
+    }
+
+    // DO UPDATE rather than DO NOTHING so the existing row's id comes back
+    let row = sqlx::query!(
+        r#"
+        INSERT INTO artwork (content_hash, mime_type, width, height, size_bytes)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (content_hash) DO UPDATE SET content_hash = EXCLUDED.content_hash
+        RETURNING id
+        "#,
+        content_hash,
+        format.to_mime_type(),
+        width as i32,
+        height as i32,
+        size_bytes
+    )
+    .fetch_one(&app.db)
+    .await?;
+    Ok(row.id)
+}
+
+/// Artwork for a track: the embedded picture if there is one, otherwise a sidecar
+/// image in `dir`. Failures are logged, a track without artwork is not an error.
+pub async fn for_track(app: &App, embedded: Option<Vec<u8>>, dir: Option<&FsPath>) -> Option<Uuid> {
+    let data = match embedded {
+        Some(data) => data,
+        None => {
+            let sidecar = dir.and_then(find_sidecar)?;
+            match tokio::fs::read(&sidecar).await {
+                Ok(data) => data,
+                Err(e) => {
+                    eprintln!("Failed to read artwork {:?}: {}", sidecar, e);
+                    return None;
+                }
+            }
+        }
+    };
+
+    match store(app, data).await {
+        Ok(id) => Some(id),
+        Err(e) => {
+            eprintln!("Failed to store artwork: {}", e);
+            None
+        }
+    }
+}
+
+/// Deletes artwork no track points at any more, image included. `store` hands out ids
+/// before a track links to them, so this only runs while nothing is importing.
+pub async fn remove_unused(app: &App) -> anyhow::Result<u64> {
+    let rows = sqlx::query!(
+        r#"
+        DELETE FROM artwork a
+        WHERE NOT EXISTS (SELECT 1 FROM tracks t WHERE t.artwork_id = a.id)
+        RETURNING content_hash
+        "#
+    )
+    .fetch_all(&app.db)
+    .await?;
+    for row in &rows {
+        app.storage.delete(&row.content_hash).await?;
+    }
+    Ok(rows.len() as u64)
+}
+
+#[derive(Deserialize)]
+pub struct ArtworkQuery {
+    /// Longest edge in pixels; the original is served without it.
+    pub size: Option<u32>,
+}
+
+/// `GET /api/artwork/:id?size=300`
+pub async fn get_artwork(
+    Path(id): Path<Uuid>,
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ArtworkQuery>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let artwork = sqlx::query!(
+        "SELECT content_hash, mime_type, width, height, size_bytes FROM artwork WHERE id = $1",
+        id
+    )
+    .fetch_optional(&state.app.db)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+    let longest = artwork.width.max(artwork.height) as u32;
+    let size = query
+        .size
+        .map(|size| size.clamp(MIN_SIZE, MAX_SIZE))
+        .filter(|size| *size < longest);
+
+    let Some(size) = size else {
+        // Never upscale, the original is as good as it gets
+        let etag = format!("\"{}\"", artwork.content_hash);
+        if cache::not_modified(&headers, &etag, None) {
+            return Ok(cache::not_modified_response(
+                &etag,
+                None,
+                cache::ARTWORK_CACHE_CONTROL,
+            ));
+        }
+        let data = storage::read_all(
+            state.app.storage.as_ref(),
+            &artwork.content_hash,
+            artwork.size_bytes as u64,
+        )
+        .await
+        .map_err(|e| {
+            eprintln!("Error reading artwork {}: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+        return Ok(image_response(&artwork.mime_type, &etag, data.into()));
+    };
+
+    // WebP output is lossless, so it only beats JPEG for sources that were lossless too
+    let lossless = matches!(artwork.mime_type.as_str(), "image/png" | "image/webp");
+    let format = if lossless && transcode::accepts(&headers, "image/webp") {
+        ImageFormat::WebP
+    } else {
+        ImageFormat::Jpeg
+    };
+    let extension = format.extensions_str()[0];
+
+    let etag = format!("\"{}-{}.{}\"", artwork.content_hash, size, extension);
+    if cache::not_modified(&headers, &etag, None) {
+        return Ok(cache::not_modified_response(
+            &etag,
+            None,
+            cache::ARTWORK_CACHE_CONTROL,
+        ));
+    }
+
+    let key = TranscodeCache::key(&artwork.content_hash, extension, Some(size));
+    let app = &state.app;
+    let produce = || async move {
+        let data = storage::read_all(
+            app.storage.as_ref(),
+            &artwork.content_hash,
+            artwork.size_bytes as u64,
+        )
+        .await?;
+        let resized = tokio::task::spawn_blocking(move || resize(&data, size, format)).await??;
+        Ok(Bytes::from(resized))
+    };
+    let data = app
+        .transcodes
+        .get_or_insert_bytes(&key, produce)
+        .await
+        .map_err(|e| {
+            eprintln!("Error resizing artwork {}: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(image_response(format.to_mime_type(), &etag, data))
+}
+
+fn image_response(mime_type: &str, etag: &str, data: Bytes) -> Response {
+    (
+        [
+            (header::CONTENT_TYPE, mime_type),
+            (header::ETAG, etag),
+            (header::CACHE_CONTROL, cache::ARTWORK_CACHE_CONTROL),
+            (header::VARY, "Accept"),
+        ],
+        data,
+    )
+        .into_response()
+}
+
+/// Scales the image to fit in a `size` square, keeping its aspect ratio.
+fn resize(data: &[u8], size: u32, format: ImageFormat) -> anyhow::Result<Vec<u8>> {
+    let image = image::load_from_memory(data)?.resize(size, size, FilterType::CatmullRom);
+    let mut out = Vec::new();
+    match format {
+        ImageFormat::WebP => image
+            .to_rgba8()
+            .write_with_encoder(WebPEncoder::new_lossless(&mut out))?,
+        _ => image
+            .to_rgb8()
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut out, JPEG_QUALITY))?,
+    }
+    Ok(out)
+}
@@ -13,15 +13,17 @@ use axum::{
 };
 use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
 use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
 use std::sync::Arc;
 use time::{Duration, OffsetDateTime};
+use uuid::Uuid;
 use validator::Validate;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Claims {
-    sub: String, // user
-    exp: usize,  // expiration
-    iat: usize,  // issued at
+    pub sub: String, // user
+    pub exp: usize,  // expiration
+    pub iat: usize,  // issued at
 }
 
 pub enum AuthError {
@@ -113,6 +115,19 @@ pub async fn login(
     Ok(Json(AuthBody::new(token)))
 }
 
+/// The id of the user a token was issued to. A token can outlive its account, which
+/// makes it as good as no token.
+pub async fn user_id(db: &PgPool, claims: &Claims) -> Result<Uuid, StatusCode> {
+    sqlx::query_scalar!("SELECT id FROM users WHERE username = $1", claims.sub)
+        .fetch_optional(db)
+        .await
+        .map_err(|e| {
+            eprintln!("Error looking up user: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::UNAUTHORIZED)
+}
+
 pub async fn auth_middleware(
     State(_state): State<Arc<AppState>>,
     mut req: Request,
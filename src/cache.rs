@@ -0,0 +1,205 @@
+//! Validators and conditional requests (RFC 7232) for audio, artwork and JSON responses.
+
+use axum::{
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::time::SystemTime;
+
+/// Audio for a given content hash never changes, but a track id can be re-pointed
+/// at new content, so clients still revalidate every hour.
+pub const AUDIO_CACHE_CONTROL: &str = "public, max-age=3600";
+/// JSON is cheap to revalidate and changes whenever the library does.
+pub const JSON_CACHE_CONTROL: &str = "no-cache";
+/// Artwork URLs are keyed by an id whose content never changes.
+pub const ARTWORK_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+/// Whether the client's cached copy is still current, in which case a 304 is sent.
+/// `If-None-Match` takes precedence; `If-Modified-Since` is only consulted without it.
+pub fn not_modified(headers: &HeaderMap, etag: &str, last_modified: Option<SystemTime>) -> bool {
+    if let Some(if_none_match) = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|h| h.to_str().ok())
+    {
+        return etag_list_matches(if_none_match, etag);
+    }
+
+    let since = headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| httpdate::parse_http_date(h).ok());
+    match (since, last_modified) {
+        // HTTP dates have whole-second precision
+        (Some(since), Some(modified)) => {
+            httpdate::parse_http_date(&httpdate::fmt_http_date(modified))
+                .is_ok_and(|modified| modified <= since)
+        }
+        _ => false,
+    }
+}
+
+/// Weak comparison against a comma separated `If-None-Match` list.
+fn etag_list_matches(list: &str, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+    list.split(',')
+        .map(str::trim)
+        .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == etag)
+}
+
+/// 304 carrying the validators the full response would have had.
+pub fn not_modified_response(
+    etag: &str,
+    last_modified: Option<SystemTime>,
+    cache_control: &str,
+) -> Response {
+    let mut response = StatusCode::NOT_MODIFIED.into_response();
+    let headers = response.headers_mut();
+    set_header(headers, header::ETAG, etag);
+    set_header(headers, header::CACHE_CONTROL, cache_control);
+    if let Some(modified) = last_modified {
+        set_header(
+            headers,
+            header::LAST_MODIFIED,
+            &httpdate::fmt_http_date(modified),
+        );
+    }
+    response
+}
+
+/// Serialises `value` with a weak ETag over the body, answering 304 when it matches.
+pub fn json<T: Serialize>(headers: &HeaderMap, value: &T) -> Response {
+    let body = match serde_json::to_vec(value) {
+        Ok(body) => body,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+    let digest = format!("{:x}", Sha256::digest(&body));
+    let etag = format!("W/\"{}\"", &digest[..32]);
+
+    if not_modified(headers, &etag, None) {
+        return not_modified_response(&etag, None, JSON_CACHE_CONTROL);
+    }
+
+    (
+        [
+            (header::CONTENT_TYPE, "application/json"),
+            (header::ETAG, etag.as_str()),
+            (header::CACHE_CONTROL, JSON_CACHE_CONTROL),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+fn set_header(headers: &mut HeaderMap, name: header::HeaderName, value: &str) {
+    if let Ok(value) = HeaderValue::from_str(value) {
+        headers.insert(name, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const ETAG: &str = "\"abc\"";
+
+    fn headers(pairs: &[(header::HeaderName, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(name, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    fn modified() -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(784_111_777)
+    }
+
+    #[test]
+    fn etag_list_exact_match() {
+        assert!(etag_list_matches("\"abc\"", ETAG));
+        assert!(!etag_list_matches("\"abd\"", ETAG));
+    }
+
+    #[test]
+    fn etag_list_any_member() {
+        assert!(etag_list_matches("\"x\", \"abc\" ,\"y\"", ETAG));
+        assert!(!etag_list_matches("\"x\", \"y\"", ETAG));
+    }
+
+    #[test]
+    fn etag_list_star_matches_anything() {
+        assert!(etag_list_matches("*", ETAG));
+    }
+
+    #[test]
+    fn etag_list_comparison_is_weak() {
+        assert!(etag_list_matches("W/\"abc\"", ETAG));
+        assert!(etag_list_matches("\"abc\"", "W/\"abc\""));
+        assert!(etag_list_matches("W/\"abc\"", "W/\"abc\""));
+    }
+
+    #[test]
+    fn no_validators_is_modified() {
+        assert!(!not_modified(&HeaderMap::new(), ETAG, Some(modified())));
+    }
+
+    #[test]
+    fn if_none_match() {
+        let matching = headers(&[(header::IF_NONE_MATCH, ETAG)]);
+        assert!(not_modified(&matching, ETAG, None));
+        let stale = headers(&[(header::IF_NONE_MATCH, "\"old\"")]);
+        assert!(!not_modified(&stale, ETAG, None));
+    }
+
+    #[test]
+    fn if_none_match_takes_precedence_over_if_modified_since() {
+        let headers = headers(&[
+            (header::IF_NONE_MATCH, "\"old\""),
+            (header::IF_MODIFIED_SINCE, "Sun, 06 Nov 1994 08:49:37 GMT"),
+        ]);
+        assert!(!not_modified(&headers, ETAG, Some(modified())));
+    }
+
+    #[test]
+    fn if_modified_since() {
+        let same = headers(&[(header::IF_MODIFIED_SINCE, "Sun, 06 Nov 1994 08:49:37 GMT")]);
+        assert!(not_modified(&same, ETAG, Some(modified())));
+        let later = headers(&[(header::IF_MODIFIED_SINCE, "Mon, 07 Nov 1994 08:49:37 GMT")]);
+        assert!(not_modified(&later, ETAG, Some(modified())));
+        let earlier = headers(&[(header::IF_MODIFIED_SINCE, "Sat, 05 Nov 1994 08:49:37 GMT")]);
+        assert!(!not_modified(&earlier, ETAG, Some(modified())));
+    }
+
+    #[test]
+    fn if_modified_since_ignores_subsecond_precision() {
+        let headers = headers(&[(header::IF_MODIFIED_SINCE, "Sun, 06 Nov 1994 08:49:37 GMT")]);
+        let modified = modified() + Duration::from_millis(500);
+        assert!(not_modified(&headers, ETAG, Some(modified)));
+    }
+
+    #[test]
+    fn if_modified_since_needs_a_date() {
+        let invalid = headers(&[(header::IF_MODIFIED_SINCE, "yesterday")]);
+        assert!(!not_modified(&invalid, ETAG, Some(modified())));
+        let valid = headers(&[(header::IF_MODIFIED_SINCE, "Sun, 06 Nov 1994 08:49:37 GMT")]);
+        assert!(!not_modified(&valid, ETAG, None));
+    }
+
+    #[test]
+    fn json_revalidates_against_its_own_etag() {
+        let first = json(&HeaderMap::new(), &[1, 2, 3]);
+        assert_eq!(first.status(), StatusCode::OK);
+        let etag = first.headers()[header::ETAG].to_str().unwrap();
+        assert!(etag.starts_with("W/\""));
+
+        let again = json(&headers(&[(header::IF_NONE_MATCH, etag)]), &[1, 2, 3]);
+        assert_eq!(again.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(again.headers()[header::ETAG], etag);
+
+        let changed = json(&headers(&[(header::IF_NONE_MATCH, etag)]), &[1, 2]);
+        assert_eq!(changed.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,473 @@
+//! Artists and albums as entities of their own, linked from the free-text tags.
+//!
+//! Artist tags are split into credits ("EsDeeKid ft Timothée Chalamet" is EsDeeKid,
+//! featuring Timothée Chalamet), and names are matched on a normalized form, so case,
+//! accents, punctuation, `&` vs `and` and a leading "The" don't make a new artist.
+
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::Response,
+};
+use deunicode::deunicode;
+use serde::Serialize;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::app::App;
+use crate::cache;
+use crate::metadata::TrackMetadata;
+use crate::models::TrackRecord;
+use crate::AppState;
+
+/// Markers that introduce featured artists, longest first so `feat.` wins over `feat`.
+const FEATURING: &[&str] = &["featuring", "feat.", "feat", "ft.", "ft", "with"];
+
+/// Key that name variants share.
+pub fn normalize(name: &str) -> String {
+    let folded = deunicode(name).to_lowercase().replace('&', " and ");
+    let words: Vec<&str> = folded
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .collect();
+    match words.as_slice() {
+        ["the", rest @ ..] if !rest.is_empty() => rest.join(" "),
+        words => words.join(" "),
+    }
+}
+
+/// Splits `s` at a featuring marker: what comes before it, and the featured names.
+/// `with` only counts in brackets, too many real names contain it.
+fn split_featuring(s: &str) -> (&str, Option<&str>) {
+    let word_at = |at: usize, word: &str| {
+        s.get(at..at + word.len())
+            .is_some_and(|w| w.eq_ignore_ascii_case(word))
+            && s[at + word.len()..].starts_with(' ')
+    };
+
+    for (start, c) in s.char_indices() {
+        let bracketed = c == '(' || c == '[';
+        if !bracketed && start > 0 && !s[..start].ends_with(' ') {
+            continue;
+        }
+        let marker_at = start + usize::from(bracketed);
+        let Some(marker) = FEATURING
+            .iter()
+            .filter(|m| bracketed || **m != "with")
+            .find(|m| word_at(marker_at, m))
+        else {
+            continue;
+        };
+
+        // Names end at a bracket either way: "ft. X (Remix)", "(feat. X)"
+        let rest = &s[marker_at + marker.len()..];
+        let names = rest.split(['(', '[', ')', ']']).next().unwrap_or(rest);
+        return (s[..start].trim(), Some(names.trim()));
+    }
+    (s.trim(), None)
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Credits {
+    pub main: Vec<String>,
+    pub featured: Vec<String>,
+}
+
+/// Credits from an artist tag, plus any `(feat. X)` in the title.
+pub fn parse_credits(artist: &str, title: &str) -> Credits {
+    let (main, featured) = split_featuring(artist);
+    let mut credits = Credits::default();
+
+    // Only the tag separator for main artists: "Simon & Garfunkel" and "AC/DC" are one act
+    for name in main.split(';') {
+        push_unique(&mut credits.main, &[], name);
+    }
+    let from_title = split_featuring(title).1;
+    for names in featured.into_iter().chain(from_title) {
+        for name in names.split([',', '&']).flat_map(|n| n.split(" and ")) {
+            let (main, featured) = (&credits.main, &mut credits.featured);
+            push_unique(featured, main, name);
+        }
+    }
+    credits
+}
+
+fn push_unique(names: &mut Vec<String>, also_in: &[String], name: &str) {
+    let name = name.trim();
+    let key = normalize(name);
+    if key.is_empty() || names.iter().chain(also_in).any(|n| normalize(n) == key) {
+        return;
+    }
+    names.push(name.to_string());
+}
+
+/// Finds or creates the artist. The first spelling seen is the one displayed.
+async fn artist_id(app: &App, name: &str) -> anyhow::Result<Uuid> {
+    let row = sqlx::query!(
+        r#"
+        INSERT INTO artists (name, normalized_name) VALUES ($1, $2)
+        ON CONFLICT (normalized_name) DO UPDATE SET normalized_name = EXCLUDED.normalized_name
+        RETURNING id
+        "#,
+        name,
+        normalize(name)
+    )
+    .fetch_one(&app.db)
+    .await?;
+    Ok(row.id)
+}
+
+/// Links a track to its artists and album, replacing any earlier credits.
+pub async fn link_track(
+    app: &App,
+    track_id: Uuid,
+    title: &str,
+    meta: &TrackMetadata,
+) -> anyhow::Result<()> {
+    let credits = meta
+        .artist
+        .as_deref()
+        .map(|artist| parse_credits(artist, title))
+        .unwrap_or_default();
+
+    let mut artists = Vec::new();
+    for (role, names) in [("main", &credits.main), ("featured", &credits.featured)] {
+        for name in names {
+            artists.push((artist_id(app, name).await?, role));
+        }
+    }
+
+    let album_id = match meta.album.as_deref().filter(|a| !normalize(a).is_empty()) {
+        Some(album) => {
+            let album_artist = match meta.album_artist.as_deref() {
+                Some(name) if !normalize(name).is_empty() => Some(artist_id(app, name).await?),
+                _ => artists.first().map(|(id, _)| *id),
+            };
+            let row = sqlx::query!(
+                r#"
+                INSERT INTO albums (title, normalized_title, artist_id, year)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (normalized_title, COALESCE(artist_id, '00000000-0000-0000-0000-000000000000'))
+                    DO UPDATE SET year = COALESCE(albums.year, EXCLUDED.year)
+                RETURNING id
+                "#,
+                album,
+                normalize(album),
+                album_artist,
+                meta.year
+            )
+            .fetch_one(&app.db)
+            .await?;
+            Some(row.id)
+        }
+        None => None,
+    };
+
+    let mut tx = app.db.begin().await?;
+    sqlx::query!("DELETE FROM track_artists WHERE track_id = $1", track_id)
+        .execute(&mut *tx)
+        .await?;
+    for (position, (artist_id, role)) in artists.into_iter().enumerate() {
+        sqlx::query!(
+            r#"
+            INSERT INTO track_artists (track_id, artist_id, role, position)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT DO NOTHING
+            "#,
+            track_id,
+            artist_id,
+            role,
+            position as i32
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    sqlx::query!(
+        "UPDATE tracks SET album_id = $2 WHERE id = $1",
+        track_id,
+        album_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+pub struct ArtistSummary {
+    pub id: Uuid,
+    pub name: String,
+    pub track_count: i64,
+    pub album_count: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ArtistRef {
+    pub id: Uuid,
+    pub name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AlbumSummary {
+    pub id: Uuid,
+    pub title: String,
+    pub year: Option<i32>,
+    /// Artwork of the album's first track that has any.
+    pub artwork_id: Option<Uuid>,
+    pub track_count: i64,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct CreditedTrack {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub track: TrackRecord,
+    /// `main` or `featured`.
+    pub role: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ArtistWithWork {
+    #[serde(flatten)]
+    pub artist: ArtistRef,
+    /// Albums the artist is the album artist of.
+    pub albums: Vec<AlbumSummary>,
+    /// Every track the artist is credited on, featured spots included.
+    pub tracks: Vec<CreditedTrack>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AlbumWithTracks {
+    #[serde(flatten)]
+    pub album: AlbumSummary,
+    pub artist: Option<ArtistRef>,
+    /// In disc and track order.
+    pub tracks: Vec<TrackRecord>,
+    /// Sum of the tracks' durations; tracks of unknown length count as zero.
+    pub total_duration_ms: i64,
+}
+
+fn db_error(what: &str) -> impl FnOnce(sqlx::Error) -> StatusCode + '_ {
+    move |e| {
+        eprintln!("Error fetching {}: {}", what, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    }
+}
+
+/// `GET /api/artists`, artists with at least one track in the library.
+pub async fn list_artists(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let artists = sqlx::query_as!(
+        ArtistSummary,
+        r#"
+        SELECT a.id, a.name,
+               COUNT(DISTINCT t.id) AS "track_count!",
+               COUNT(DISTINCT al.id) AS "album_count!"
+        FROM artists a
+        JOIN track_artists ta ON ta.artist_id = a.id
+        JOIN tracks t ON t.id = ta.track_id AND t.removed_at IS NULL
+        LEFT JOIN albums al ON al.id = t.album_id AND al.artist_id = a.id
+        GROUP BY a.id
+        ORDER BY a.normalized_name
+        "#
+    )
+    .fetch_all(&state.app.db)
+    .await
+    .map_err(db_error("artists"))?;
+
+    Ok(cache::json(&headers, &artists))
+}
+
+/// `GET /api/artists/:id`
+pub async fn get_artist(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let artist = sqlx::query_as!(ArtistRef, "SELECT id, name FROM artists WHERE id = $1", id)
+        .fetch_optional(&state.app.db)
+        .await
+        .map_err(db_error("artist"))?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let albums = sqlx::query_as!(
+        AlbumSummary,
+        r#"
+        SELECT al.id, al.title, al.year,
+               (SELECT t.artwork_id FROM tracks t
+                WHERE t.album_id = al.id AND t.artwork_id IS NOT NULL
+                ORDER BY t.disc_number, t.track_number LIMIT 1) AS artwork_id,
+               COUNT(t.id) AS "track_count!"
+        FROM albums al
+        JOIN tracks t ON t.album_id = al.id AND t.removed_at IS NULL
+        WHERE al.artist_id = $1
+        GROUP BY al.id
+        ORDER BY al.year NULLS LAST, al.normalized_title
+        "#,
+        id
+    )
+    .fetch_all(&state.app.db)
+    .await
+    .map_err(db_error("artist albums"))?;
+
+    let tracks = sqlx::query_as::<sqlx::Postgres, CreditedTrack>(&format!(
+        r#"
+        SELECT {}, ta.role
+        FROM tracks t
+        JOIN track_artists ta ON ta.track_id = t.id
+        LEFT JOIN albums al ON al.id = t.album_id
+        WHERE ta.artist_id = $1 AND t.removed_at IS NULL
+        ORDER BY al.year NULLS LAST, al.normalized_title NULLS LAST,
+                 t.disc_number NULLS FIRST, t.track_number NULLS LAST, t.title
+        "#,
+        TrackRecord::columns("t")
+    ))
+    .bind(id)
+    .fetch_all(&state.app.db)
+    .await
+    .map_err(db_error("artist tracks"))?;
+
+    Ok(cache::json(
+        &headers,
+        &ArtistWithWork {
+            artist,
+            albums,
+            tracks,
+        },
+    ))
+}
+
+/// `GET /api/albums/:id`
+pub async fn get_album(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let row = sqlx::query!(
+        r#"
+        SELECT al.id, al.title, al.year, a.id AS "artist_id?", a.name AS "artist_name?"
+        FROM albums al
+        LEFT JOIN artists a ON a.id = al.artist_id
+        WHERE al.id = $1
+        "#,
+        id
+    )
+    .fetch_optional(&state.app.db)
+    .await
+    .map_err(db_error("album"))?
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+    let tracks = sqlx::query_as::<sqlx::Postgres, TrackRecord>(&format!(
+        r#"
+        SELECT {}
+        FROM tracks t
+        WHERE t.album_id = $1 AND t.removed_at IS NULL
+        ORDER BY t.disc_number NULLS FIRST, t.track_number NULLS LAST, t.title
+        "#,
+        TrackRecord::columns("t")
+    ))
+    .bind(id)
+    .fetch_all(&state.app.db)
+    .await
+    .map_err(db_error("album tracks"))?;
+
+    let total_duration_ms = tracks
+        .iter()
+        .filter_map(|t| t.duration_ms)
+        .map(i64::from)
+        .sum();
+    let artist = row
+        .artist_id
+        .zip(row.artist_name)
+        .map(|(id, name)| ArtistRef { id, name });
+
+    Ok(cache::json(
+        &headers,
+        &AlbumWithTracks {
+            album: AlbumSummary {
+                id: row.id,
+                title: row.title,
+                year: row.year,
+                artwork_id: tracks.iter().find_map(|t| t.artwork_id),
+                track_count: tracks.len() as i64,
+            },
+            artist,
+            tracks,
+            total_duration_ms,
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn credits(main: &[&str], featured: &[&str]) -> Credits {
+        Credits {
+            main: main.iter().map(|s| s.to_string()).collect(),
+            featured: featured.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn normalize_folds_case_accents_punctuation_and_the() {
+        assert_eq!(normalize("Beyoncé"), "beyonce");
+        assert_eq!(normalize("The Beatles"), "beatles");
+        assert_eq!(normalize("Beatles, The"), "beatles the");
+        assert_eq!(normalize("The The"), "the");
+        assert_eq!(normalize("The"), "the");
+        assert_eq!(normalize("Simon & Garfunkel"), "simon and garfunkel");
+        assert_eq!(normalize("AC/DC"), "ac dc");
+        assert_eq!(normalize("  Sigur   Rós "), "sigur ros");
+        assert_eq!(normalize("!!!"), "");
+    }
+
+    #[test]
+    fn split_featuring_finds_markers_at_word_starts() {
+        assert_eq!(
+            split_featuring("EsDeeKid ft Timothée Chalamet"),
+            ("EsDeeKid", Some("Timothée Chalamet"))
+        );
+        assert_eq!(split_featuring("A feat. B"), ("A", Some("B")));
+        assert_eq!(split_featuring("A FEATURING B"), ("A", Some("B")));
+        assert_eq!(split_featuring("Song (feat. B)"), ("Song", Some("B")));
+        assert_eq!(split_featuring("Song [ft. B] (Remix)"), ("Song", Some("B")));
+        assert_eq!(split_featuring("A ft. B (Remix)"), ("A", Some("B")));
+        assert_eq!(split_featuring("Song (with B)"), ("Song", Some("B")));
+        // Not markers: inside words, or an unbracketed "with"
+        assert_eq!(split_featuring("Swift Left"), ("Swift Left", None));
+        assert_eq!(split_featuring("Heft"), ("Heft", None));
+        assert_eq!(
+            split_featuring("Dancing with Myself"),
+            ("Dancing with Myself", None)
+        );
+    }
+
+    #[test]
+    fn parse_credits_splits_main_and_featured_artists() {
+        assert_eq!(
+            parse_credits("EsDeeKid ft Timothée Chalamet", "4 Raws (Remix)"),
+            credits(&["EsDeeKid"], &["Timothée Chalamet"])
+        );
+        assert_eq!(
+            parse_credits("A; B", "Song (feat. C, D & E and F)"),
+            credits(&["A", "B"], &["C", "D", "E", "F"])
+        );
+        // One act, not two
+        assert_eq!(
+            parse_credits("Simon & Garfunkel", "The Boxer"),
+            credits(&["Simon & Garfunkel"], &[])
+        );
+    }
+
+    #[test]
+    fn parse_credits_drops_repeats_and_main_artists_from_featured() {
+        assert_eq!(
+            parse_credits("A feat. B", "Song (feat. b, A)"),
+            credits(&["A"], &["B"])
+        );
+        assert_eq!(parse_credits("A; a; ", "Song"), credits(&["A"], &[]));
+    }
+}
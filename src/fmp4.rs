@@ -0,0 +1,609 @@
+//! Fragmented MP4 (ISO BMFF) with FLAC audio, the lossless HLS rendition format.
+//!
+//! The file is an init section (`ftyp` + `moov`) followed by one `moof` + `mdat` pair per
+//! segment, so a playlist can address segments as byte ranges of a single cached file.
+//! FLAC frames are written verbatim (uncompressed), which keeps the encoder trivial. The
+//! lossy rungs of the ladder are AAC fragments from ffmpeg, laid out the same way.
+
+use std::io::{Read, Seek, SeekFrom, Write};
+
+/// Samples per FLAC frame, and per MP4 sample.
+const BLOCK_SIZE: usize = 4096;
+/// Segments are cut on frame boundaries as close to this as possible.
+pub const SEGMENT_SECONDS: f64 = 6.0;
+const TRACK_ID: u32 = 1;
+
+/// A media segment's byte range within the file and its duration.
+#[derive(Debug, Clone, Copy)]
+pub struct Segment {
+    pub offset: u64,
+    pub len: u64,
+    pub duration: f64,
+}
+
+/// Writes a fragmented MP4 a frame at a time, holding back at most one segment.
+pub struct Writer<W: Write> {
+    out: W,
+    channels: usize,
+    blocks_per_segment: usize,
+    /// Interleaved samples of the block being filled.
+    block: Vec<i16>,
+    /// (duration, size) of each FLAC frame in `mdat`.
+    samples: Vec<(u32, u32)>,
+    mdat: Vec<u8>,
+    frame_number: u64,
+    sequence: u32,
+    decode_time: u64,
+}
+
+impl<W: Write> Writer<W> {
+    /// Writes the init section. The stream's length isn't known up front, so STREAMINFO
+    /// leaves it out.
+    pub fn new(mut out: W, rate: u32, channels: u32) -> std::io::Result<Self> {
+        out.write_all(&init_section(rate, channels, 0))?;
+        let channels = channels as usize;
+        Ok(Self {
+            out,
+            channels,
+            blocks_per_segment: ((SEGMENT_SECONDS * f64::from(rate) / BLOCK_SIZE as f64).round()
+                as usize)
+                .max(1),
+            block: Vec::with_capacity(BLOCK_SIZE * channels),
+            samples: Vec::new(),
+            mdat: Vec::new(),
+            frame_number: 0,
+            sequence: 0,
+            decode_time: 0,
+        })
+    }
+
+    /// Adds one interleaved frame.
+    pub fn push(&mut self, frame: &[i16]) -> std::io::Result<()> {
+        self.block.extend_from_slice(frame);
+        if self.block.len() == BLOCK_SIZE * self.channels {
+            self.end_block();
+            if self.samples.len() == self.blocks_per_segment {
+                self.write_segment()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes whatever is left as a final, shorter segment.
+    pub fn finish(mut self) -> std::io::Result<W> {
+        if !self.block.is_empty() {
+            self.end_block();
+        }
+        if !self.samples.is_empty() {
+            self.write_segment()?;
+        }
+        Ok(self.out)
+    }
+
+    fn end_block(&mut self) {
+        let frame = flac_frame(self.frame_number, &self.block, self.channels);
+        self.samples.push((
+            (self.block.len() / self.channels) as u32,
+            frame.len() as u32,
+        ));
+        self.mdat.extend_from_slice(&frame);
+        self.frame_number += 1;
+        self.block.clear();
+    }
+
+    fn write_segment(&mut self) -> std::io::Result<()> {
+        self.sequence += 1;
+        self.out
+            .write_all(&moof(self.sequence, self.decode_time, &self.samples))?;
+        self.out.write_all(&mp4_box(b"mdat", &self.mdat))?;
+        self.decode_time += self.samples.iter().map(|(d, _)| u64::from(*d)).sum::<u64>();
+        self.samples.clear();
+        self.mdat.clear();
+        Ok(())
+    }
+}
+
+/// Length of the init section and the segments that follow it, read back from a file
+/// produced by [`Writer`] or ffmpeg. Only the `moov` and `moof` boxes are read; audio
+/// is skipped.
+pub fn segments(mut file: impl Read + Seek) -> anyhow::Result<(u64, Vec<Segment>)> {
+    let mut init_len = 0;
+    let mut timescale = 0;
+    let mut default_duration = 0;
+    let mut segments = Vec::new();
+    let mut pending: Option<(u64, u64, u64)> = None;
+
+    let mut offset = 0u64;
+    let mut header = [0u8; 8];
+    loop {
+        match file.read_exact(&mut header) {
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            result => result?,
+        }
+        let len = u64::from(u32::from_be_bytes(header[..4].try_into()?));
+        if len < 8 {
+            anyhow::bail!("Unsupported box size {}", len);
+        }
+        let kind = &header[4..8];
+        let body = if kind == b"moov" || kind == b"moof" {
+            let mut body = vec![0; (len - 8) as usize];
+            file.read_exact(&mut body)?;
+            body
+        } else {
+            file.seek(SeekFrom::Start(offset + len))?;
+            Vec::new()
+        };
+
+        match kind {
+            b"moov" => {
+                let mdhd = find(&body, &[b"trak", b"mdia", b"mdhd"])
+                    .ok_or_else(|| anyhow::anyhow!("Missing mdhd"))?;
+                timescale = read_u32(mdhd, 12)?;
+                if let Some(trex) = find(&body, &[b"mvex", b"trex"]) {
+                    default_duration = read_u32(trex, 12)?;
+                }
+                init_len = offset + len;
+            }
+            b"moof" => {
+                let traf =
+                    find(&body, &[b"traf"]).ok_or_else(|| anyhow::anyhow!("Missing traf"))?;
+                pending = Some((offset, len, traf_duration(traf, default_duration)?));
+            }
+            b"mdat" => {
+                let (start, moof_len, duration) = pending
+                    .take()
+                    .ok_or_else(|| anyhow::anyhow!("mdat without moof"))?;
+                segments.push(Segment {
+                    offset: start,
+                    len: moof_len + len,
+                    duration: duration as f64 / f64::from(timescale.max(1)),
+                });
+            }
+            _ => {}
+        }
+        offset += len;
+    }
+
+    Ok((init_len, segments))
+}
+
+fn init_section(rate: u32, channels: u32, total_frames: u64) -> Vec<u8> {
+    let ftyp = mp4_box(
+        b"ftyp",
+        &[b"iso6".as_slice(), &0u32.to_be_bytes(), b"iso6mp41"].concat(),
+    );
+
+    let matrix: Vec<u8> = [0x10000u32, 0, 0, 0, 0x10000, 0, 0, 0, 0x4000_0000]
+        .iter()
+        .flat_map(|v| v.to_be_bytes())
+        .collect();
+
+    let mut mvhd = Vec::new();
+    mvhd.extend_from_slice(&[0; 8]); // creation, modification time
+    mvhd.extend_from_slice(&1000u32.to_be_bytes());
+    mvhd.extend_from_slice(&0u32.to_be_bytes()); // duration lives in the fragments
+    mvhd.extend_from_slice(&0x10000u32.to_be_bytes()); // rate 1.0
+    mvhd.extend_from_slice(&0x100u16.to_be_bytes()); // volume 1.0
+    mvhd.extend_from_slice(&[0; 10]);
+    mvhd.extend_from_slice(&matrix);
+    mvhd.extend_from_slice(&[0; 24]);
+    mvhd.extend_from_slice(&(TRACK_ID + 1).to_be_bytes());
+
+    let mut tkhd = Vec::new();
+    tkhd.extend_from_slice(&[0; 8]);
+    tkhd.extend_from_slice(&TRACK_ID.to_be_bytes());
+    tkhd.extend_from_slice(&[0; 4]);
+    tkhd.extend_from_slice(&0u32.to_be_bytes()); // duration
+    tkhd.extend_from_slice(&[0; 8]);
+    tkhd.extend_from_slice(&[0; 4]); // layer, alternate group
+    tkhd.extend_from_slice(&0x100u16.to_be_bytes());
+    tkhd.extend_from_slice(&[0; 2]);
+    tkhd.extend_from_slice(&matrix);
+    tkhd.extend_from_slice(&[0; 8]); // width, height
+
+    let mut mdhd = Vec::new();
+    mdhd.extend_from_slice(&[0; 8]);
+    mdhd.extend_from_slice(&rate.to_be_bytes());
+    mdhd.extend_from_slice(&0u32.to_be_bytes());
+    mdhd.extend_from_slice(&0x55c4u16.to_be_bytes()); // "und"
+    mdhd.extend_from_slice(&[0; 2]);
+
+    let mut hdlr = Vec::new();
+    hdlr.extend_from_slice(&[0; 4]);
+    hdlr.extend_from_slice(b"soun");
+    hdlr.extend_from_slice(&[0; 12]);
+    hdlr.extend_from_slice(b"SoundHandler\0");
+
+    let dref = full_box(
+        b"dref",
+        0,
+        0,
+        &[&1u32.to_be_bytes()[..], &full_box(b"url ", 0, 1, &[])].concat(),
+    );
+    let stbl = mp4_box(
+        b"stbl",
+        &[
+            full_box(
+                b"stsd",
+                0,
+                0,
+                &[
+                    &1u32.to_be_bytes()[..],
+                    &flac_sample_entry(rate, channels, total_frames),
+                ]
+                .concat(),
+            ),
+            full_box(b"stts", 0, 0, &0u32.to_be_bytes()),
+            full_box(b"stsc", 0, 0, &0u32.to_be_bytes()),
+            full_box(b"stsz", 0, 0, &[0; 8]),
+            full_box(b"stco", 0, 0, &0u32.to_be_bytes()),
+        ]
+        .concat(),
+    );
+    let minf = mp4_box(
+        b"minf",
+        &[
+            full_box(b"smhd", 0, 0, &[0; 4]),
+            mp4_box(b"dinf", &dref),
+            stbl,
+        ]
+        .concat(),
+    );
+    let mdia = mp4_box(
+        b"mdia",
+        &[
+            full_box(b"mdhd", 0, 0, &mdhd),
+            full_box(b"hdlr", 0, 0, &hdlr),
+            minf,
+        ]
+        .concat(),
+    );
+    let trak = mp4_box(b"trak", &[full_box(b"tkhd", 0, 3, &tkhd), mdia].concat());
+
+    let mut trex = Vec::new();
+    trex.extend_from_slice(&TRACK_ID.to_be_bytes());
+    trex.extend_from_slice(&1u32.to_be_bytes()); // sample description index
+    trex.extend_from_slice(&[0; 12]); // default duration, size, flags
+    let mvex = mp4_box(b"mvex", &full_box(b"trex", 0, 0, &trex));
+
+    let moov = mp4_box(
+        b"moov",
+        &[full_box(b"mvhd", 0, 0, &mvhd), trak, mvex].concat(),
+    );
+    [ftyp, moov].concat()
+}
+
+/// `fLaC` sample entry carrying the stream's STREAMINFO in a `dfLa` box.
+fn flac_sample_entry(rate: u32, channels: u32, total_frames: u64) -> Vec<u8> {
+    let mut streaminfo = Vec::with_capacity(34);
+    streaminfo.extend_from_slice(&(BLOCK_SIZE as u16).to_be_bytes());
+    streaminfo.extend_from_slice(&(BLOCK_SIZE as u16).to_be_bytes());
+    streaminfo.extend_from_slice(&[0; 6]); // frame sizes unknown
+    let packed = (u64::from(rate) << 44)
+        | (u64::from(channels - 1) << 41)
+        | (15 << 36) // 16 bits per sample
+        | (total_frames & 0xf_ffff_ffff);
+    streaminfo.extend_from_slice(&packed.to_be_bytes());
+    streaminfo.extend_from_slice(&[0; 16]); // MD5 unknown
+
+    let mut dfla = vec![0x80, 0, 0, 34]; // last metadata block, STREAMINFO
+    dfla.extend_from_slice(&streaminfo);
+
+    let mut entry = Vec::new();
+    entry.extend_from_slice(&[0; 6]);
+    entry.extend_from_slice(&1u16.to_be_bytes()); // data reference index
+    entry.extend_from_slice(&[0; 8]);
+    entry.extend_from_slice(&(channels as u16).to_be_bytes());
+    entry.extend_from_slice(&16u16.to_be_bytes());
+    entry.extend_from_slice(&[0; 4]);
+    // 16.16 fixed point; the real rate is in STREAMINFO when it doesn't fit
+    let rate_field = if rate <= 0xffff { rate << 16 } else { 0 };
+    entry.extend_from_slice(&rate_field.to_be_bytes());
+    entry.extend_from_slice(&full_box(b"dfLa", 0, 0, &dfla));
+    mp4_box(b"fLaC", &entry)
+}
+
+/// `moof` for one segment; `samples` are (duration, size) pairs.
+fn moof(sequence: u32, decode_time: u64, samples: &[(u32, u32)]) -> Vec<u8> {
+    let mfhd = full_box(b"mfhd", 0, 0, &sequence.to_be_bytes());
+    // default-base-is-moof: data offsets are relative to the start of this box
+    let tfhd = full_box(b"tfhd", 0, 0x02_0000, &TRACK_ID.to_be_bytes());
+    let tfdt = full_box(b"tfdt", 1, 0, &decode_time.to_be_bytes());
+
+    let trun_len = 12 + 4 + 4 + samples.len() * 8;
+    let moof_len = 8 + mfhd.len() + 8 + tfhd.len() + tfdt.len() + trun_len;
+
+    let mut trun = Vec::with_capacity(trun_len);
+    trun.extend_from_slice(&(samples.len() as u32).to_be_bytes());
+    // Audio starts right after the mdat header
+    trun.extend_from_slice(&((moof_len + 8) as u32).to_be_bytes());
+    for (duration, size) in samples {
+        trun.extend_from_slice(&duration.to_be_bytes());
+        trun.extend_from_slice(&size.to_be_bytes());
+    }
+    // data offset, sample duration and sample size present
+    let trun = full_box(b"trun", 0, 0x000301, &trun);
+
+    let traf = mp4_box(b"traf", &[tfhd, tfdt, trun].concat());
+    mp4_box(b"moof", &[mfhd, traf].concat())
+}
+
+/// One fixed-blocksize FLAC frame with verbatim subframes.
+fn flac_frame(number: u64, samples: &[i16], channels: usize) -> Vec<u8> {
+    let block_size = samples.len() / channels;
+    let mut frame = Vec::with_capacity(16 + samples.len() * 2 + channels);
+    // Sync code, fixed blocksize; blocksize as 16 bit at end of header, rate from STREAMINFO
+    frame.extend_from_slice(&[0xff, 0xf8, 0x70]);
+    frame.push((((channels - 1) as u8) << 4) | 0x08); // independent channels, 16 bits
+    push_utf8_number(&mut frame, number);
+    frame.extend_from_slice(&((block_size - 1) as u16).to_be_bytes());
+    frame.push(crc8(&frame));
+
+    for channel in 0..channels {
+        frame.push(0x02); // verbatim subframe, no wasted bits
+        for sample in samples.iter().skip(channel).step_by(channels) {
+            frame.extend_from_slice(&sample.to_be_bytes());
+        }
+    }
+
+    let crc = crc16(&frame);
+    frame.extend_from_slice(&crc.to_be_bytes());
+    frame
+}
+
+/// FLAC's UTF-8-like variable length coding of frame numbers.
+fn push_utf8_number(out: &mut Vec<u8>, value: u64) {
+    if value < 0x80 {
+        out.push(value as u8);
+        return;
+    }
+    let len = match value {
+        0..=0x7ff => 2,
+        0x800..=0xffff => 3,
+        0x1_0000..=0x1f_ffff => 4,
+        0x20_0000..=0x3ff_ffff => 5,
+        _ => 6,
+    };
+    let lead_mask = !(0xffu8 >> len);
+    out.push(lead_mask | (value >> (6 * (len - 1))) as u8);
+    for i in (0..len - 1).rev() {
+        out.push(0x80 | ((value >> (6 * i)) & 0x3f) as u8);
+    }
+}
+
+fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |mut crc, &byte| {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0u16, |mut crc, &byte| {
+        crc ^= u16::from(byte) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+fn mp4_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(8 + body.len());
+    out.extend_from_slice(&((8 + body.len()) as u32).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(body);
+    out
+}
+
+fn full_box(kind: &[u8; 4], version: u8, flags: u32, body: &[u8]) -> Vec<u8> {
+    let header = (u32::from(version) << 24) | (flags & 0xff_ffff);
+    mp4_box(kind, &[&header.to_be_bytes()[..], body].concat())
+}
+
+/// Top-level boxes of `data` as (type, offset, body).
+fn boxes(data: &[u8]) -> impl Iterator<Item = ([u8; 4], u64, &[u8])> {
+    let mut offset = 0usize;
+    std::iter::from_fn(move || {
+        let header = data.get(offset..offset + 8)?;
+        let len = u32::from_be_bytes(header[..4].try_into().ok()?) as usize;
+        let kind: [u8; 4] = header[4..8].try_into().ok()?;
+        let body = data.get(offset + 8..offset + len.max(8))?;
+        let start = offset as u64;
+        offset += len.max(8);
+        Some((kind, start, body))
+    })
+}
+
+/// Body of the box at `path`, descending through container boxes.
+fn find<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> Option<&'a [u8]> {
+    let (first, rest) = path.split_first()?;
+    let (_, _, body) = boxes(data).find(|(kind, _, _)| kind == *first)?;
+    if rest.is_empty() {
+        Some(body)
+    } else {
+        find(body, rest)
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> anyhow::Result<u32> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_be_bytes(b.try_into().unwrap()))
+        .ok_or_else(|| anyhow::anyhow!("Truncated box"))
+}
+
+/// Sum of the sample durations in a `traf` body. Samples without durations of their
+/// own take the `tfhd` default, or failing that the `trex` one.
+fn traf_duration(traf: &[u8], default_duration: u32) -> anyhow::Result<u64> {
+    let tfhd = find(traf, &[b"tfhd"]).ok_or_else(|| anyhow::anyhow!("Missing tfhd"))?;
+    let tfhd_flags = read_u32(tfhd, 0)? & 0xff_ffff;
+    let mut default_duration = default_duration;
+    if tfhd_flags & 0x08 != 0 {
+        // After the track id, and the base data offset and sample description if present
+        let mut offset = 8;
+        if tfhd_flags & 0x01 != 0 {
+            offset += 8;
+        }
+        if tfhd_flags & 0x02 != 0 {
+            offset += 4;
+        }
+        default_duration = read_u32(tfhd, offset)?;
+    }
+
+    let trun = find(traf, &[b"trun"]).ok_or_else(|| anyhow::anyhow!("Missing trun"))?;
+    let flags = read_u32(trun, 0)? & 0xff_ffff;
+    let count = read_u32(trun, 4)? as usize;
+    if flags & 0x100 == 0 {
+        return Ok(count as u64 * u64::from(default_duration));
+    }
+    let mut offset = 8;
+    if flags & 0x1 != 0 {
+        offset += 4;
+    }
+    if flags & 0x4 != 0 {
+        offset += 4;
+    }
+    let per_sample = [0x100, 0x200, 0x400, 0x800]
+        .iter()
+        .filter(|f| flags & **f != 0)
+        .count()
+        * 4;
+
+    let mut total = 0;
+    for i in 0..count {
+        total += u64::from(read_u32(trun, offset + i * per_sample)?);
+    }
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn box_sizes_include_the_header() {
+        let plain = mp4_box(b"free", &[1, 2, 3]);
+        assert_eq!(plain, [0, 0, 0, 11, b'f', b'r', b'e', b'e', 1, 2, 3]);
+        let full = full_box(b"mfhd", 1, 0x02_0001, &[9]);
+        assert_eq!(full, [0, 0, 0, 13, b'm', b'f', b'h', b'd', 1, 2, 0, 1, 9]);
+    }
+
+    #[test]
+    fn crcs_match_the_check_values() {
+        // CRC-8 (poly 0x07) and CRC-16/UMTS (poly 0x8005), both starting from zero
+        assert_eq!(crc8(b"123456789"), 0xf4);
+        assert_eq!(crc16(b"123456789"), 0xfee8);
+        assert_eq!(crc8(&[]), 0);
+        assert_eq!(crc16(&[]), 0);
+    }
+
+    #[test]
+    fn frame_numbers_use_utf8_coding() {
+        let cases: &[(u64, &[u8])] = &[
+            (0, &[0x00]),
+            (0x7f, &[0x7f]),
+            (0x80, &[0xc2, 0x80]),
+            (0x7ff, &[0xdf, 0xbf]),
+            (0x800, &[0xe0, 0xa0, 0x80]),
+            (0xffff, &[0xef, 0xbf, 0xbf]),
+            (0x1_0000, &[0xf0, 0x90, 0x80, 0x80]),
+            (0x20_0000, &[0xf8, 0x88, 0x80, 0x80, 0x80]),
+            (0x400_0000, &[0xfc, 0x84, 0x80, 0x80, 0x80, 0x80]),
+        ];
+        for (value, expected) in cases {
+            let mut out = Vec::new();
+            push_utf8_number(&mut out, *value);
+            assert_eq!(out, *expected, "{:#x}", value);
+        }
+    }
+
+    #[test]
+    fn frame_headers_are_encoded() {
+        let samples: Vec<i16> = (0..8).collect();
+        let frame = flac_frame(0x80, &samples, 2);
+        // Sync, fixed blocksize; 16 bit blocksize at the end; stereo, 16 bits
+        assert_eq!(frame[..4], [0xff, 0xf8, 0x70, 0x18]);
+        assert_eq!(frame[4..6], [0xc2, 0x80]);
+        assert_eq!(frame[6..8], 3u16.to_be_bytes());
+        assert_eq!(frame[8], crc8(&frame[..8]));
+        // Verbatim subframes, one channel after the other
+        assert_eq!(frame[9], 0x02);
+        assert_eq!(frame[10..18], [0, 0, 0, 2, 0, 4, 0, 6]);
+        assert_eq!(frame[18], 0x02);
+        assert_eq!(frame[19..27], [0, 1, 0, 3, 0, 5, 0, 7]);
+        let (body, crc) = frame.split_at(frame.len() - 2);
+        assert_eq!(crc, crc16(body).to_be_bytes());
+    }
+
+    #[test]
+    fn moof_data_offset_points_past_the_mdat_header() {
+        let moof = moof(3, 4096, &[(4096, 100), (10, 20)]);
+        assert_eq!(moof.len(), read_u32(&moof, 0).unwrap() as usize);
+        let traf = find(&moof[8..], &[b"traf"]).unwrap();
+        let trun = find(traf, &[b"trun"]).unwrap();
+        assert_eq!(read_u32(trun, 4).unwrap(), 2);
+        assert_eq!(read_u32(trun, 8).unwrap() as usize, moof.len() + 8);
+        assert_eq!(traf_duration(traf, 0).unwrap(), 4106);
+    }
+
+    #[test]
+    fn segments_are_read_back() {
+        let rate = 44100;
+        let frames = rate as usize * 13;
+        let mut writer = Writer::new(Vec::new(), rate, 1).unwrap();
+        for i in 0..frames {
+            writer.push(&[i as i16]).unwrap();
+        }
+        let file = writer.finish().unwrap();
+
+        let (init_len, segments) = segments(Cursor::new(&file)).unwrap();
+        let boxes: Vec<_> = boxes(&file).map(|(kind, _, _)| kind).collect();
+        assert_eq!(boxes[..2], [*b"ftyp", *b"moov"]);
+        assert_eq!(init_len, segments[0].offset);
+        assert_eq!(segments.len(), 3);
+        let end = segments.last().map(|s| s.offset + s.len).unwrap();
+        assert_eq!(end, file.len() as u64);
+        for pair in segments.windows(2) {
+            assert_eq!(pair[0].offset + pair[0].len, pair[1].offset);
+        }
+        // 65 blocks of 4096 frames to a segment, as close to six seconds as it gets
+        assert!((segments[0].duration - 65.0 * 4096.0 / 44100.0).abs() < 1e-9);
+        let total: f64 = segments.iter().map(|s| s.duration).sum();
+        assert!((total - 13.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn default_durations_come_from_tfhd_or_trex() {
+        // ffmpeg style: no per-sample durations in trun
+        let trun = full_box(
+            b"trun",
+            0,
+            0x000201,
+            &[&3u32.to_be_bytes()[..], &[0; 16]].concat(),
+        );
+        let tfhd = full_box(
+            b"tfhd",
+            0,
+            0x02_0008,
+            &[&TRACK_ID.to_be_bytes()[..], &1024u32.to_be_bytes()].concat(),
+        );
+        let traf = [tfhd, trun.clone()].concat();
+        assert_eq!(traf_duration(&traf, 0).unwrap(), 3 * 1024);
+
+        let tfhd = full_box(b"tfhd", 0, 0x02_0000, &TRACK_ID.to_be_bytes());
+        let traf = [tfhd, trun].concat();
+        assert_eq!(traf_duration(&traf, 960).unwrap(), 3 * 960);
+    }
+}
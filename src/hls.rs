@@ -0,0 +1,172 @@
+//! HLS playlists for adaptive playback.
+//!
+//! Each variant is one fragmented MP4 rendition from the transcode cache, served by
+//! `/api/stream/:id?format=fmp4&bitrate=` like any other transcode. Segments are byte
+//! ranges of it, so Range handling, ETags and caching all come for free. The ladder is
+//! AAC at a few bitrates, when there's an encoder for it, topped by lossless FLAC.
+//!
+//! `?gain=track` or `?gain=album` on the master playlist carries through to every
+//! variant and segment, like it does on a plain stream.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use std::fmt::Write;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::fmp4;
+use crate::stream::{requested_gain, stored_track, transcode_track};
+use crate::transcode::Format;
+use crate::AppState;
+
+/// AAC variants offered below the source quality, in kbps.
+const VARIANT_BITRATES: &[u32] = &[96, 192];
+/// Room for container overhead on top of the audio bitrate.
+const OVERHEAD_PERCENT: u64 = 5;
+const PLAYLIST_CONTENT_TYPE: &str = "application/vnd.apple.mpegurl";
+
+fn playlist_response(body: String) -> Response {
+    (
+        [
+            (header::CONTENT_TYPE, PLAYLIST_CONTENT_TYPE),
+            (header::CACHE_CONTROL, "no-cache"),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+#[derive(Deserialize)]
+pub struct HlsQuery {
+    /// `track` or `album`, as on `/api/stream/:id`.
+    pub gain: Option<String>,
+}
+
+fn media_uri(id: Uuid, bitrate: Option<u32>, query: &HlsQuery) -> String {
+    let mut uri = format!("/api/stream/{}?format=fmp4", id);
+    if let Some(bitrate) = bitrate {
+        write!(uri, "&bitrate={}", bitrate).ok();
+    }
+    if let Some(gain) = &query.gain {
+        write!(uri, "&gain={}", gain).ok();
+    }
+    uri
+}
+
+/// Whether symphonia's codec name is a lossless one.
+fn is_lossless(codec: &str) -> bool {
+    matches!(codec, "flac" | "alac") || codec.starts_with("pcm")
+}
+
+/// `GET /api/stream/:id/hls/master.m3u8`
+pub async fn master_playlist(
+    Path(id): Path<Uuid>,
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<HlsQuery>,
+) -> Result<Response, StatusCode> {
+    if !matches!(query.gain.as_deref(), None | Some("track" | "album")) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let track = sqlx::query!(
+        "SELECT codec, sample_rate, channels FROM tracks WHERE id = $1",
+        id
+    )
+    .fetch_optional(&state.app.db)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .ok_or(StatusCode::NOT_FOUND)?;
+    // Probed at import; without them the track couldn't be decoded either
+    let (Some(rate), Some(channels)) = (track.sample_rate, track.channels) else {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    };
+
+    let mut variants = Vec::new();
+    if state.app.encoder.is_some() {
+        for &bitrate in VARIANT_BITRATES {
+            let bandwidth = u64::from(bitrate) * 1000 * (100 + OVERHEAD_PERCENT) / 100;
+            variants.push((bitrate.to_string(), bandwidth, "mp4a.40.2"));
+        }
+    }
+    // Lossless on top, unless it would only blow a lossy source up to PCM size
+    if variants.is_empty() || track.codec.as_deref().is_some_and(is_lossless) {
+        // Verbatim FLAC is PCM plus a little framing, for at most two channels
+        let bandwidth = (rate as u64) * (channels.clamp(1, 2) as u64) * 16 * 101 / 100;
+        variants.push(("source".to_string(), bandwidth, "fLaC"));
+    }
+
+    let mut playlist = String::from("#EXTM3U\n#EXT-X-VERSION:7\n#EXT-X-INDEPENDENT-SEGMENTS\n");
+    let suffix = query
+        .gain
+        .map_or(String::new(), |gain| format!("?gain={}", gain));
+    for (name, bandwidth, codecs) in variants {
+        writeln!(
+            playlist,
+            "#EXT-X-STREAM-INF:BANDWIDTH={},CODECS=\"{}\"\n/api/stream/{}/hls/{}.m3u8{}",
+            bandwidth, codecs, id, name, suffix
+        )
+        .ok();
+    }
+    Ok(playlist_response(playlist))
+}
+
+/// `GET /api/stream/:id/hls/:variant.m3u8`, where the variant is `source` or a bitrate.
+pub async fn variant_playlist(
+    Path((id, variant)): Path<(Uuid, String)>,
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<HlsQuery>,
+) -> Result<Response, StatusCode> {
+    let bitrate = match variant.strip_suffix(".m3u8").ok_or(StatusCode::NOT_FOUND)? {
+        "source" => None,
+        bitrate => Some(
+            bitrate
+                .parse::<u32>()
+                .ok()
+                .filter(|b| VARIANT_BITRATES.contains(b) && state.app.encoder.is_some())
+                .ok_or(StatusCode::NOT_FOUND)?,
+        ),
+    };
+
+    let track = stored_track(&state, id).await?;
+    let gain = requested_gain(query.gain.as_deref(), &track)?;
+    let rendition = transcode_track(&state, id, &track, Format::Fmp4, bitrate, gain).await?;
+    let (init_len, segments) = rendition.blocking_read(fmp4::segments).await.map_err(|e| {
+        eprintln!("Error reading rendition of track {}: {}", id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let uri = media_uri(id, bitrate, &query);
+    let target = segments
+        .iter()
+        .map(|s| s.duration.ceil() as u64)
+        .max()
+        .unwrap_or(1);
+
+    let mut playlist = String::new();
+    writeln!(playlist, "#EXTM3U\n#EXT-X-VERSION:7").ok();
+    writeln!(playlist, "#EXT-X-TARGETDURATION:{}", target).ok();
+    writeln!(
+        playlist,
+        "#EXT-X-PLAYLIST-TYPE:VOD\n#EXT-X-INDEPENDENT-SEGMENTS"
+    )
+    .ok();
+    writeln!(
+        playlist,
+        "#EXT-X-MAP:URI=\"{}\",BYTERANGE=\"{}@0\"",
+        uri, init_len
+    )
+    .ok();
+    for segment in segments {
+        writeln!(
+            playlist,
+            "#EXTINF:{:.6},\n#EXT-X-BYTERANGE:{}@{}\n{}",
+            segment.duration, segment.len, segment.offset, uri
+        )
+        .ok();
+    }
+    playlist.push_str("#EXT-X-ENDLIST\n");
+    Ok(playlist_response(playlist))
+}
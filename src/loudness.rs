@@ -0,0 +1,337 @@
+//! EBU R128 loudness analysis (ITU-R BS.1770-4): gated integrated loudness and true
+//! peak, plus the ReplayGain 2.0 style gains derived from them.
+//!
+//! Only the front pair is measured; anything beyond two channels is ignored.
+
+use std::io::Cursor;
+
+use crate::transcode;
+
+/// ReplayGain 2.0 reference level; gains bring tracks here.
+pub const REFERENCE_LUFS: f64 = -18.0;
+/// Applied gain never pushes the true peak above this.
+pub const PEAK_CEILING_DBTP: f64 = -1.0;
+
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const RELATIVE_GATE_LU: f64 = -10.0;
+/// Gating blocks are 400 ms, overlapping by 75%, so they're built from 100 ms steps.
+const STEPS_PER_BLOCK: usize = 4;
+const OVERSAMPLE: usize = 4;
+/// Taps per phase of the true-peak interpolator.
+const TAPS: usize = 12;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Loudness {
+    /// Integrated loudness in LUFS; silence reads as the absolute gate, -70.
+    pub integrated: f64,
+    /// True peak in dBTP.
+    pub true_peak: f64,
+}
+
+impl Loudness {
+    /// Gain in dB that brings the track to the reference level.
+    pub fn track_gain(&self) -> f64 {
+        REFERENCE_LUFS - self.integrated
+    }
+}
+
+/// `gain` in dB, lowered if needed to keep a track peaking at `true_peak` under the ceiling.
+pub fn limit_gain(gain: f64, true_peak: f64) -> f64 {
+    gain.min(PEAK_CEILING_DBTP - true_peak)
+}
+
+/// Decodes `data` and measures it.
+pub fn analyze(data: Vec<u8>, mime_type: &str) -> anyhow::Result<Loudness> {
+    let mut meter = None;
+    transcode::visit_frames(Cursor::new(data), mime_type, |rate, channels, _| {
+        let meter = meter.insert(Meter::new(rate, channels as usize));
+        move |frame: &[f32]| meter.push(frame)
+    })?;
+    meter
+        .map(Meter::finish)
+        .ok_or_else(|| anyhow::anyhow!("No audio decoded"))
+}
+
+fn lufs(mean_square: f64) -> f64 {
+    -0.691 + 10.0 * mean_square.log10()
+}
+
+struct Meter {
+    /// K-weighting per channel: high shelf, then high pass.
+    filters: Vec<[Biquad; 2]>,
+    peaks: Vec<TruePeak>,
+    step_len: usize,
+    step_fill: usize,
+    step_energy: f64,
+    /// Sum of squares of each complete 100 ms step, over all channels.
+    steps: Vec<f64>,
+}
+
+impl Meter {
+    fn new(rate: u32, channels: usize) -> Self {
+        let rate = f64::from(rate);
+        Self {
+            filters: (0..channels)
+                .map(|_| [Biquad::high_shelf(rate), Biquad::high_pass(rate)])
+                .collect(),
+            peaks: (0..channels).map(|_| TruePeak::new()).collect(),
+            step_len: (rate / 10.0).round() as usize,
+            step_fill: 0,
+            step_energy: 0.0,
+            steps: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, frame: &[f32]) {
+        for ((filters, peak), &sample) in self.filters.iter_mut().zip(&mut self.peaks).zip(frame) {
+            peak.push(sample);
+            let [shelf, high_pass] = filters;
+            let weighted = high_pass.process(shelf.process(f64::from(sample)));
+            self.step_energy += weighted * weighted;
+        }
+        self.step_fill += 1;
+        if self.step_fill == self.step_len {
+            self.steps.push(self.step_energy);
+            self.step_fill = 0;
+            self.step_energy = 0.0;
+        }
+    }
+
+    fn finish(self) -> Loudness {
+        let block_len = (self.step_len * STEPS_PER_BLOCK) as f64;
+        let blocks: Vec<f64> = self
+            .steps
+            .windows(STEPS_PER_BLOCK)
+            .map(|w| w.iter().sum::<f64>() / block_len)
+            .filter(|&z| z > 0.0 && lufs(z) > ABSOLUTE_GATE_LUFS)
+            .collect();
+
+        let integrated = if blocks.is_empty() {
+            ABSOLUTE_GATE_LUFS
+        } else {
+            let mean = |blocks: &[f64]| blocks.iter().sum::<f64>() / blocks.len() as f64;
+            let threshold = lufs(mean(&blocks)) + RELATIVE_GATE_LU;
+            let gated: Vec<f64> = blocks
+                .into_iter()
+                .filter(|&z| lufs(z) > threshold)
+                .collect();
+            lufs(mean(&gated))
+        };
+
+        let peak = self.peaks.iter().map(|p| p.max).fold(0.0, f64::max);
+        Loudness {
+            integrated,
+            true_peak: 20.0 * peak.max(1e-10).log10(),
+        }
+    }
+}
+
+/// Transposed direct form II biquad.
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    /// Stage one of the K-weighting curve, the head's acoustic effect. BS.1770 only
+    /// gives coefficients for 48 kHz; these are derived from its analog prototype.
+    fn high_shelf(rate: f64) -> Self {
+        let f0 = 1681.974450955533;
+        let gain_db = 3.999843853973347;
+        let q = 0.7071752369554196;
+
+        let k = (std::f64::consts::PI * f0 / rate).tan();
+        let vh = 10f64.powf(gain_db / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        Self {
+            b: [
+                (vh + vb * k / q + k * k) / a0,
+                2.0 * (k * k - vh) / a0,
+                (vh - vb * k / q + k * k) / a0,
+            ],
+            a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            z: [0.0; 2],
+        }
+    }
+
+    /// Stage two, the RLB high pass.
+    fn high_pass(rate: f64) -> Self {
+        let f0 = 38.13547087602444;
+        let q = 0.5003270373238773;
+
+        let k = (std::f64::consts::PI * f0 / rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+        Self {
+            b: [1.0, -2.0, 1.0],
+            a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            z: [0.0; 2],
+        }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[0] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+/// Peak of the signal oversampled 4x with a windowed-sinc interpolator, per BS.1770 Annex 2.
+struct TruePeak {
+    /// Ring of the last `TAPS` samples, oldest at `next`.
+    history: [f64; TAPS],
+    next: usize,
+    /// Taps for the points 1/4, 2/4 and 3/4 of the way through the middle pair.
+    phases: [[f64; TAPS]; OVERSAMPLE - 1],
+    max: f64,
+}
+
+impl TruePeak {
+    fn new() -> Self {
+        let half = (TAPS / 2) as f64;
+        let mut phases = [[0.0; TAPS]; OVERSAMPLE - 1];
+        for (p, taps) in phases.iter_mut().enumerate() {
+            let point = half - 1.0 + (p + 1) as f64 / OVERSAMPLE as f64;
+            for (k, tap) in taps.iter_mut().enumerate() {
+                let x = k as f64 - point;
+                let sinc = if x == 0.0 {
+                    1.0
+                } else {
+                    (std::f64::consts::PI * x).sin() / (std::f64::consts::PI * x)
+                };
+                let window = 0.5 * (1.0 + (std::f64::consts::PI * x / half).cos());
+                *tap = sinc * window;
+            }
+        }
+        Self {
+            history: [0.0; TAPS],
+            next: 0,
+            phases,
+            max: 0.0,
+        }
+    }
+
+    fn push(&mut self, sample: f32) {
+        let sample = f64::from(sample);
+        self.history[self.next] = sample;
+        self.next = (self.next + 1) % TAPS;
+        self.max = self.max.max(sample.abs());
+
+        for taps in &self.phases {
+            let value: f64 = taps
+                .iter()
+                .enumerate()
+                .map(|(k, tap)| tap * self.history[(self.next + k) % TAPS])
+                .sum();
+            self.max = self.max.max(value.abs());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48000;
+
+    /// Measures a stereo sine of `hz` made of `(seconds, dBFS)` sections, the way the
+    /// EBU Tech 3341 test signals are described. Sections are shorter than the spec's to
+    /// keep debug builds quick, which puts a little more weight on blocks straddling them.
+    fn measure_at(rate: u32, hz: f64, sections: &[(f64, f64)]) -> Loudness {
+        let mut meter = Meter::new(rate, 2);
+        let mut n = 0u64;
+        for &(seconds, dbfs) in sections {
+            let amplitude = 10f64.powf(dbfs / 20.0);
+            for _ in 0..(seconds * f64::from(rate)) as u64 {
+                let phase = 2.0 * std::f64::consts::PI * hz * n as f64 / f64::from(rate);
+                let sample = (amplitude * phase.sin()) as f32;
+                meter.push(&[sample, sample]);
+                n += 1;
+            }
+        }
+        meter.finish()
+    }
+
+    fn measure(sections: &[(f64, f64)]) -> Loudness {
+        measure_at(RATE, 997.0, sections)
+    }
+
+    fn assert_near(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "{} is not within {} of {}",
+            actual,
+            tolerance,
+            expected
+        );
+    }
+
+    #[test]
+    fn sine_at_reference_level_reads_minus_23_lufs() {
+        // Tech 3341 case 1
+        assert_near(measure(&[(4.0, -23.0)]).integrated, -23.0, 0.1);
+        // Case 2
+        assert_near(measure(&[(4.0, -33.0)]).integrated, -33.0, 0.1);
+        // The K-weighting filters are derived for the rate, not just tabled for 48 kHz
+        assert_near(
+            measure_at(44100, 997.0, &[(4.0, -23.0)]).integrated,
+            -23.0,
+            0.1,
+        );
+    }
+
+    #[test]
+    fn relative_gate_ignores_quiet_passages() {
+        // Tech 3341 case 3
+        let loudness = measure(&[(2.0, -36.0), (20.0, -23.0), (2.0, -36.0)]);
+        assert_near(loudness.integrated, -23.0, 0.1);
+    }
+
+    #[test]
+    fn absolute_gate_ignores_near_silence() {
+        // Tech 3341 case 4
+        let loudness = measure(&[
+            (2.0, -72.0),
+            (2.0, -36.0),
+            (20.0, -23.0),
+            (2.0, -36.0),
+            (2.0, -72.0),
+        ]);
+        assert_near(loudness.integrated, -23.0, 0.1);
+    }
+
+    #[test]
+    fn silence_reads_as_the_absolute_gate() {
+        let mut meter = Meter::new(RATE, 2);
+        for _ in 0..RATE * 2 {
+            meter.push(&[0.0, 0.0]);
+        }
+        assert_eq!(meter.finish().integrated, ABSOLUTE_GATE_LUFS);
+    }
+
+    #[test]
+    fn true_peak_finds_peaks_between_samples() {
+        // A quarter-rate sine sampled 45 degrees off its peaks: every sample is at
+        // -3 dBFS, but the waveform between them reaches 0 dBTP
+        let mut meter = Meter::new(RATE, 1);
+        for n in 0..RATE {
+            let phase = std::f64::consts::FRAC_PI_2 * n as f64 + std::f64::consts::FRAC_PI_4;
+            meter.push(&[phase.sin() as f32]);
+        }
+        // BS.1770 allows true peak meters to read between -0.4 and +0.2 dB off
+        assert_near(meter.finish().true_peak, 0.0, 0.4);
+    }
+
+    #[test]
+    fn gain_is_limited_by_the_peak_ceiling() {
+        let loudness = Loudness {
+            integrated: -23.0,
+            true_peak: -3.0,
+        };
+        assert_eq!(loudness.track_gain(), 5.0);
+        assert_eq!(limit_gain(loudness.track_gain(), loudness.true_peak), 2.0);
+        assert_eq!(limit_gain(-4.0, loudness.true_peak), -4.0);
+    }
+}
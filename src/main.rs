@@ -1,22 +1,43 @@
 use axum::{
-    body::Body,
-    extract::{Path, State},
-    http::{Method, StatusCode},
+    extract::{DefaultBodyLimit, Query, State},
+    http::{HeaderMap, StatusCode},
     response::{IntoResponse, Response},
-    routing::{delete, get, post},
-    Json, Router,
+    routing::{get, post},
+    Router,
 };
-use std::{net::SocketAddr, sync::Arc};
+use std::{net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
 use tower_http::cors::{Any, CorsLayer};
 
+mod admin;
 mod app;
+mod artwork;
 mod auth;
+mod cache;
+mod catalog;
 mod db;
+mod fmp4;
+mod hls;
+mod loudness;
+mod metadata;
 mod models;
+mod page;
 mod playlist;
-
-use crate::app::App;
-use crate::models::TrackRecord;
+mod range;
+mod scan;
+mod search;
+mod storage;
+mod stream;
+mod transcode;
+mod transcode_cache;
+mod upload;
+mod watcher;
+mod waveform;
+
+use crate::app::{App, ImportPolicy, ImportStatus};
+use crate::models::TrackListQuery;
+use crate::page::Cursor;
+use crate::scan::ScanOptions;
+use crate::transcode_cache::TranscodeCache;
 
 struct AppState {
     app: App, // access db directly via app.db or just keep app
@@ -28,52 +49,160 @@ async fn main() {
 
     let pool = db::init_db_pool().await.unwrap();
 
-    // Initialize App with DB
-    let app = App::new(pool.clone());
+    // Initialize App with DB, audio storage and the transcode cache
+    let storage = storage::from_env().unwrap();
+    let transcodes = TranscodeCache::from_env().unwrap();
+    let app = App::new(pool.clone(), storage, transcodes);
+    if app.encoder.is_none() {
+        println!("ffmpeg not found, lossy formats will fall back to the original");
+    }
+
+    // `arcsin migrate-storage` moves audio still in tracks.data out to the storage backend
+    if std::env::args().nth(1).as_deref() == Some("migrate-storage") {
+        match app.migrate_data_to_storage().await {
+            Ok(n) => println!("Moved {} tracks to storage", n),
+            Err(e) => eprintln!("Storage migration failed: {}", e),
+        }
+        return;
+    }
+
+    // Library directories, comma separated, defaulting to ./assets
+    let library_dirs: Vec<PathBuf> = std::env::var("LIBRARY_DIRS")
+        .unwrap_or_else(|_| "assets".to_string())
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .filter_map(|dir| match std::fs::canonicalize(dir) {
+            Ok(dir) => Some(dir),
+            Err(e) => {
+                eprintln!("Skipping library directory {:?}: {}", dir, e);
+                None
+            }
+        })
+        .collect();
 
     // Scan and import tracks
-    let current_dir = std::env::current_dir().unwrap();
-    let assets_dir = current_dir.join("assets");
-    println!("Scanning directory: {:?}", assets_dir);
-    if let Err(e) = app.import_tracks_from_dir(&assets_dir).await {
-        eprintln!("Failed to import tracks: {}", e);
+    let policy = ImportPolicy::from_env().unwrap();
+    let scan_options = ScanOptions::from_env().unwrap();
+    for dir in &library_dirs {
+        println!("Scanning directory: {:?} (policy: {:?})", dir, policy);
+        match app.import_tracks_from_dir(dir, &scan_options, &policy).await {
+            Ok(report) => {
+                println!(
+                    "Imported {} new tracks ({} filename clashes), updated {}, skipped {} duplicates, {} failed",
+                    report.count(ImportStatus::New) + report.count(ImportStatus::FilenameClash),
+                    report.count(ImportStatus::FilenameClash),
+                    report.count(ImportStatus::Updated),
+                    report.count(ImportStatus::DuplicateContent),
+                    report.failures.len()
+                );
+                for failure in &report.failures {
+                    eprintln!("  {:?}: {}", failure.path, failure.error);
+                }
+            }
+            Err(e) => eprintln!("Failed to import tracks: {}", e),
+        }
+    }
+
+    // Cover art left behind by tracks whose tags changed, while nothing else is importing
+    match artwork::remove_unused(&app).await {
+        Ok(0) => {}
+        Ok(n) => println!("Removed {} unused artwork images", n),
+        Err(e) => eprintln!("Failed to remove unused artwork: {}", e),
+    }
+
+    match upload::expire_stale_uploads(&app.db).await {
+        Ok(0) => {}
+        Ok(n) => println!("Removed {} stale uploads", n),
+        Err(e) => eprintln!("Failed to remove stale uploads: {}", e),
     }
 
     let state = Arc::new(AppState { app });
 
+    // Tracks imported before artists, albums, audio properties and loudness were recorded
+    let probe_state = state.clone();
+    tokio::spawn(async move {
+        match probe_state.app.link_missing_catalog().await {
+            Ok(0) => {}
+            Ok(n) => println!("Linked {} tracks to artists and albums", n),
+            Err(e) => eprintln!("Failed to link artists and albums: {}", e),
+        }
+        match probe_state.app.probe_missing_properties().await {
+            Ok(0) => {}
+            Ok(n) => println!("Probed audio properties of {} tracks", n),
+            Err(e) => eprintln!("Failed to probe audio properties: {}", e),
+        }
+        match probe_state.app.analyze_missing_loudness().await {
+            Ok(0) => {}
+            Ok(n) => println!("Measured loudness of {} tracks", n),
+            Err(e) => eprintln!("Failed to measure loudness: {}", e),
+        }
+    });
+
+    // Pick up library changes while the server runs
+    let watch = std::env::var("LIBRARY_WATCH").map_or(true, |v| v != "false");
+    if watch && !library_dirs.is_empty() {
+        let debounce = std::env::var("LIBRARY_WATCH_DEBOUNCE_MS")
+            .ok()
+            .and_then(|ms| ms.parse().ok())
+            .unwrap_or(2000);
+        if let Err(e) = watcher::spawn(
+            state.clone(),
+            library_dirs,
+            scan_options,
+            Duration::from_millis(debounce),
+        ) {
+            eprintln!("Failed to watch library directories: {}", e);
+        }
+    }
+
     // CORS
     let cors = CorsLayer::new()
         .allow_origin(Any)
         .allow_methods(Any)
         .allow_headers(Any);
 
+    let auth = axum::middleware::from_fn_with_state(state.clone(), auth::auth_middleware);
+
     // Router
     let app = Router::new()
-        .route("/api/tracks", get(list_tracks))
-        .route("/api/stream/:id", get(stream_track))
-        .route("/auth/register", post(auth::register))
-        .route("/auth/login", post(auth::login))
         .route(
-            "/api/protected",
-            get(protected).layer(axum::middleware::from_fn_with_state(
-                state.clone(),
-                auth::auth_middleware,
-            )),
+            "/api/tracks",
+            get(list_tracks).merge(
+                post(upload::upload_track)
+                    .layer(DefaultBodyLimit::disable())
+                    .layer(auth.clone()),
+            ),
         )
+        .route("/api/tracks/:id/waveform", get(waveform::get_waveform))
         .route(
-            "/api/playlists",
-            get(playlist::list_playlists).post(playlist::create_playlist),
+            "/api/uploads",
+            post(upload::create_upload).layer(auth.clone()),
         )
         .route(
-            "/api/playlists/:id", get(playlist::get_playlist).delete(playlist::delete_playlist))
-        .route(
-            "/api/playlists/:id/tracks",
-            post(playlist::add_track_to_playlist),
+            "/api/uploads/:id",
+            get(upload::get_upload)
+                .patch(upload::append_upload)
+                .delete(upload::delete_upload)
+                .layer(DefaultBodyLimit::disable())
+                .layer(auth.clone()),
         )
+        .route("/api/stream/:id", get(stream::stream_track))
+        .route("/api/stream/:id/hls/master.m3u8", get(hls::master_playlist))
+        .route("/api/stream/:id/hls/:variant", get(hls::variant_playlist))
+        .route("/api/artwork/:id", get(artwork::get_artwork))
+        .route("/api/search", get(search::search).layer(auth.clone()))
+        .route("/api/artists", get(catalog::list_artists))
+        .route("/api/artists/:id", get(catalog::get_artist))
+        .route("/api/albums/:id", get(catalog::get_album))
+        .route("/auth/register", post(auth::register))
+        .route("/auth/login", post(auth::login))
+        .route("/api/protected", get(protected).layer(auth.clone()))
         .route(
-            "/api/playlists/:id/tracks/:track_id",
-            delete(playlist::remove_track_from_playlist),
+            "/api/admin/transcode-cache",
+            get(admin::transcode_cache_stats).layer(auth),
         )
+        .merge(playlist::routes(state.clone()))
         .layer(cors)
         .with_state(state);
 
@@ -87,95 +216,23 @@ async fn protected() -> impl IntoResponse {
     "This is a protected route"
 }
 
-async fn list_tracks(State(state): State<Arc<AppState>>) -> Json<Vec<TrackRecord>> {
-    match state.app.get_tracks().await {
-        Ok(tracks) => Json(tracks),
+async fn list_tracks(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<TrackListQuery>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let limit = page::limit(query.limit)?;
+    let cursor = query
+        .cursor
+        .as_deref()
+        .map(|c| Cursor::decode(c, query.sort.name(), query.order))
+        .transpose()?;
+
+    match state.app.get_tracks(&query, cursor.as_ref(), limit).await {
+        Ok(page) => Ok(cache::json(&headers, &page)),
         Err(e) => {
             eprintln!("Error fetching tracks: {}", e);
-            Json(vec![])
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
     }
 }
-
-async fn stream_track(
-    Path(id): Path<uuid::Uuid>,
-    State(state): State<Arc<AppState>>,
-    headers: axum::http::HeaderMap,
-) -> Result<impl IntoResponse, StatusCode> {
-    // Fetch track data from DB
-    let record = sqlx::query!("SELECT data, mime_type FROM tracks WHERE id = $1", id)
-        .fetch_optional(&state.app.db)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    if let Some(record) = record {
-        let file_size = record.data.len();
-        println!("Stream request for ID: {}, Size: {}", id, file_size);
-
-        let range_header = headers
-            .get(axum::http::header::RANGE)
-            .and_then(|h| h.to_str().ok());
-
-        if let Some(range_value) = range_header {
-            println!("Received Range header: {}", range_value);
-            // Expected format: "bytes=start-end" or "bytes=start-"
-            if let Some(ranges) = range_value.strip_prefix("bytes=") {
-                let parts: Vec<&str> = ranges.split('-').collect();
-                println!("Parts: {:?}", parts);
-                if !parts.is_empty() {
-                    let start_str = parts[0];
-                    let end_str = if parts.len() > 1 { parts[1] } else { "" };
-
-                    let start = start_str.parse::<usize>().unwrap_or(0);
-                    let end = if !end_str.is_empty() {
-                        end_str.parse::<usize>().unwrap_or(file_size - 1)
-                    } else {
-                        file_size - 1
-                    };
-
-                    println!("Parsed Range: start={}, end={}", start, end);
-
-                    // Clamp end to file_size - 1
-                    let end = std::cmp::min(end, file_size - 1);
-
-                    if start > end || start >= file_size {
-                        println!("Range not satisfiable");
-                        return Err(StatusCode::RANGE_NOT_SATISFIABLE);
-                    }
-
-                    let chunk_size = end - start + 1;
-                    let data_slice = &record.data[start..=end];
-                    let body = Body::from(data_slice.to_vec());
-
-                    println!("Returning 206 Partial Content. Chunk size: {}", chunk_size);
-                    return Ok(Response::builder()
-                        .status(StatusCode::PARTIAL_CONTENT)
-                        .header(axum::http::header::CONTENT_TYPE, record.mime_type)
-                        .header(axum::http::header::ACCEPT_RANGES, "bytes")
-                        .header(
-                            axum::http::header::CONTENT_RANGE,
-                            format!("bytes {}-{}/{}", start, end, file_size),
-                        )
-                        .header(axum::http::header::CONTENT_LENGTH, chunk_size.to_string())
-                        .body(body)
-                        .unwrap());
-                }
-            } else {
-                println!("Range header did not start with bytes=");
-            }
-        } else {
-            println!("No Range header received. Returning full content.");
-        }
-
-        // Full content
-        let body = Body::from(record.data);
-        Ok(Response::builder()
-            .header(axum::http::header::CONTENT_TYPE, record.mime_type)
-            .header(axum::http::header::ACCEPT_RANGES, "bytes")
-            .header(axum::http::header::CONTENT_LENGTH, file_size.to_string())
-            .body(body)
-            .unwrap())
-    } else {
-        Err(StatusCode::NOT_FOUND)
-    }
-}
@@ -0,0 +1,272 @@
+use std::fs::File;
+use std::io::Cursor;
+use std::path::Path;
+use symphonia::core::formats::{FormatOptions, FormatReader};
+use symphonia::core::io::{MediaSource, MediaSourceStream};
+use symphonia::core::meta::{MetadataOptions, MetadataRevision, StandardTagKey, StandardVisualKey};
+use symphonia::core::probe::Hint;
+
+/// Tag metadata and audio properties for a single audio file, as stored on `tracks`.
+#[derive(Debug, Default, Clone)]
+pub struct TrackMetadata {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub track_number: Option<i32>,
+    pub disc_number: Option<i32>,
+    pub year: Option<i32>,
+    pub genre: Option<String>,
+    pub duration_ms: Option<i32>,
+    /// Short codec name from symphonia, e.g. `mp3`, `flac`, `aac`.
+    pub codec: Option<String>,
+    /// Average bitrate in bits per second.
+    pub bitrate: Option<i32>,
+    pub sample_rate: Option<i32>,
+    pub channels: Option<i32>,
+    /// Embedded cover art, front cover preferred.
+    pub artwork: Option<Vec<u8>>,
+}
+
+impl TrackMetadata {
+    /// Reads embedded tags (ID3v2, Vorbis comments, FLAC, MP4) from `path`.
+    /// Files without a title tag get their title/artist parsed from the filename.
+    pub fn read(path: &Path) -> Self {
+        let mut meta = Self::probe(path).unwrap_or_else(|e| {
+            eprintln!("Failed to read tags from {:?}: {}", path, e);
+            Self::default()
+        });
+
+        if meta.title.is_none() {
+            let fallback = Self::from_filename(path);
+            meta.title = fallback.title;
+            meta.artist = meta.artist.or(fallback.artist);
+            meta.track_number = meta.track_number.or(fallback.track_number);
+        }
+
+        meta
+    }
+
+    /// Reads tags and properties of audio that is already in memory, e.g. from storage.
+    pub fn read_bytes(data: Vec<u8>, mime_type: &str) -> anyhow::Result<Self> {
+        let mut hint = Hint::new();
+        hint.mime_type(mime_type);
+        Self::probe_source(Box::new(Cursor::new(data)), &hint)
+    }
+
+    fn probe(path: &Path) -> anyhow::Result<Self> {
+        let file = File::open(path)?;
+        let mut hint = Hint::new();
+        if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+            hint.with_extension(ext);
+        }
+        Self::probe_source(Box::new(file), &hint)
+    }
+
+    fn probe_source(source: Box<dyn MediaSource>, hint: &Hint) -> anyhow::Result<Self> {
+        let mss = MediaSourceStream::new(source, Default::default());
+        // Gapless trimming so durations match what players actually play
+        let options = FormatOptions {
+            enable_gapless: true,
+            ..Default::default()
+        };
+        let mut probed = symphonia::default::get_probe().format(
+            hint,
+            mss,
+            &options,
+            &MetadataOptions::default(),
+        )?;
+
+        let mut meta = Self::default();
+
+        // ID3v2 tags sit in front of the container and are reported by the probe,
+        // everything else (Vorbis comments, FLAC, MP4 atoms) by the format reader.
+        if let Some(rev) = probed.metadata.get().as_ref().and_then(|m| m.current()) {
+            meta.apply_tags(rev);
+        }
+        if let Some(rev) = probed.format.metadata().current() {
+            meta.apply_tags(rev);
+        }
+
+        meta.apply_properties(probed.format.as_mut());
+        Ok(meta)
+    }
+
+    /// Codec parameters from the headers, plus an exact duration and average bitrate
+    /// from walking every packet. Packets are only demuxed, not decoded.
+    fn apply_properties(&mut self, reader: &mut dyn FormatReader) {
+        let Some(track) = reader.default_track() else {
+            return;
+        };
+        let track_id = track.id;
+        let params = track.codec_params.clone();
+
+        self.codec = symphonia::default::get_codecs()
+            .get_codec(params.codec)
+            .map(|c| c.short_name.to_string());
+        self.sample_rate = params.sample_rate.and_then(|r| i32::try_from(r).ok());
+        self.channels = params.channels.map(|c| c.count() as i32);
+
+        let mut frames = 0u64;
+        let mut bytes = 0u64;
+        while let Ok(packet) = reader.next_packet() {
+            if packet.track_id() == track_id {
+                let trim = u64::from(packet.trim_start) + u64::from(packet.trim_end);
+                frames += packet.dur.saturating_sub(trim);
+                bytes += packet.buf().len() as u64;
+            }
+        }
+        // Headers are only an estimate (e.g. VBR MP3 without a Xing frame), so they're
+        // the fallback when the packets carry no timing
+        if frames == 0 {
+            frames = params.n_frames.unwrap_or(0);
+        }
+
+        let seconds = match (params.time_base, params.sample_rate) {
+            (Some(tb), _) => {
+                let time = tb.calc_time(frames);
+                time.seconds as f64 + time.frac
+            }
+            (None, Some(rate)) => frames as f64 / f64::from(rate),
+            (None, None) => 0.0,
+        };
+        if seconds > 0.0 {
+            self.duration_ms = i32::try_from((seconds * 1000.0).round() as i64).ok();
+            if bytes > 0 {
+                self.bitrate = i32::try_from((bytes as f64 * 8.0 / seconds).round() as i64).ok();
+            }
+        }
+    }
+
+    fn apply_tags(&mut self, rev: &MetadataRevision) {
+        let front = rev
+            .visuals()
+            .iter()
+            .find(|v| v.usage == Some(StandardVisualKey::FrontCover))
+            .or_else(|| rev.visuals().first());
+        if let Some(visual) = front {
+            self.artwork = Some(visual.data.to_vec());
+        }
+
+        for tag in rev.tags() {
+            let Some(key) = tag.std_key else { continue };
+            let value = tag.value.to_string().trim().to_string();
+            if value.is_empty() {
+                continue;
+            }
+
+            match key {
+                StandardTagKey::TrackTitle => self.title = Some(value),
+                StandardTagKey::Artist => self.artist = Some(value),
+                StandardTagKey::Album => self.album = Some(value),
+                StandardTagKey::AlbumArtist => self.album_artist = Some(value),
+                StandardTagKey::TrackNumber => self.track_number = leading_number(&value),
+                StandardTagKey::DiscNumber => self.disc_number = leading_number(&value),
+                StandardTagKey::Date | StandardTagKey::ReleaseDate => {
+                    self.year = self.year.or_else(|| leading_number(&value))
+                }
+                StandardTagKey::Genre => self.genre = Some(value),
+                _ => {}
+            }
+        }
+    }
+
+    /// Best-effort parse of names like `01 Title.flac` or `Artist - Title.mp3`.
+    pub fn from_filename(path: &Path) -> Self {
+        let stem = path
+            .file_stem()
+            .map(|s| s.to_string_lossy().trim().to_string())
+            .unwrap_or_default();
+
+        let mut meta = Self::default();
+        let mut rest = stem.as_str();
+
+        let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        if digits > 0 && digits <= 3 {
+            let after = rest[digits..].trim_start_matches(['.', '-', '_', ' ']);
+            let separator = &rest[digits..rest.len() - after.len()];
+            // `01 - Artist - Title` is track 1, but `50 Cent - Title` is an artist
+            let numbered = separator.contains(['.', '-', '_'])
+                || rest.starts_with('0')
+                || !after.contains(" - ");
+            if !after.is_empty() && !separator.is_empty() && numbered {
+                meta.track_number = rest[..digits].parse().ok();
+                rest = after;
+            }
+        }
+
+        match rest.split_once(" - ") {
+            Some((artist, title)) if !artist.trim().is_empty() && !title.trim().is_empty() => {
+                meta.artist = Some(artist.trim().to_string());
+                meta.title = Some(title.trim().to_string());
+            }
+            _ => meta.title = Some(rest.to_string()),
+        }
+
+        meta
+    }
+}
+
+/// Parses the leading integer of values like `3/12` or `2024-05-01`.
+fn leading_number(value: &str) -> Option<i32> {
+    let end = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    value[..end].parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(name: &str) -> (Option<i32>, Option<String>, Option<String>) {
+        let meta = TrackMetadata::from_filename(Path::new(name));
+        (meta.track_number, meta.artist, meta.title)
+    }
+
+    fn some(value: &str) -> Option<String> {
+        Some(value.to_string())
+    }
+
+    #[test]
+    fn artist_and_title_split_on_a_dash() {
+        assert_eq!(
+            parse("Artist - Title.mp3"),
+            (None, some("Artist"), some("Title"))
+        );
+        assert_eq!(parse("Title.mp3"), (None, None, some("Title")));
+    }
+
+    #[test]
+    fn leading_numbers_are_track_numbers() {
+        assert_eq!(parse("01 Title.flac"), (Some(1), None, some("Title")));
+        assert_eq!(parse("7. Title.flac"), (Some(7), None, some("Title")));
+        assert_eq!(
+            parse("01 - Artist - Title.flac"),
+            (Some(1), some("Artist"), some("Title"))
+        );
+        assert_eq!(
+            parse("12_Artist - Title.flac"),
+            (Some(12), some("Artist"), some("Title"))
+        );
+    }
+
+    #[test]
+    fn numbers_that_are_the_name_are_kept() {
+        assert_eq!(
+            parse("50 Cent - In Da Club.mp3"),
+            (None, some("50 Cent"), some("In Da Club"))
+        );
+        assert_eq!(parse("1999.mp3"), (None, None, some("1999")));
+        assert_eq!(parse("1234 Title.mp3"), (None, None, some("1234 Title")));
+    }
+
+    #[test]
+    fn leading_number_stops_at_the_first_non_digit() {
+        assert_eq!(leading_number("3/12"), Some(3));
+        assert_eq!(leading_number("12"), Some(12));
+        assert_eq!(leading_number("2024-05-01"), Some(2024));
+        assert_eq!(leading_number("/12"), None);
+        assert_eq!(leading_number(""), None);
+    }
+}
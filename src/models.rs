@@ -3,6 +3,8 @@ use time::OffsetDateTime;
 use uuid::Uuid;
 use validator::Validate;
 
+use crate::page::{Cursor, Order, SortKey};
+
 #[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
 pub struct User {
     pub id: Uuid,
@@ -18,15 +20,136 @@ pub struct TrackRecord {
     pub id: Uuid,
     pub title: String,
     pub artist: Option<String>,
+    pub album: Option<String>,
+    pub album_id: Option<Uuid>,
+    pub album_artist: Option<String>,
+    pub track_number: Option<i32>,
+    pub disc_number: Option<i32>,
+    pub year: Option<i32>,
+    pub genre: Option<String>,
+    pub duration_ms: Option<i32>,
+    pub codec: Option<String>,
+    /// Average bitrate in bits per second.
+    pub bitrate: Option<i32>,
+    pub sample_rate: Option<i32>,
+    pub channels: Option<i32>,
+    pub artwork_id: Option<Uuid>,
+    /// EBU R128 integrated loudness in LUFS.
+    pub loudness_lufs: Option<f32>,
+    pub true_peak_dbtp: Option<f32>,
+    /// Gain in dB to the -18 LUFS reference, for the track alone and as part of its album.
+    pub track_gain_db: Option<f32>,
+    pub album_gain_db: Option<f32>,
     pub filename: String,
-    // data is too large to serialize by default, usually fetched separately
-    #[serde(skip)]
-    pub data: Vec<u8>,
     pub mime_type: String,
+    pub size_bytes: i64,
+    pub content_hash: String,
+    #[serde(with = "time::serde::iso8601::option")]
+    pub removed_at: Option<OffsetDateTime>,
     #[serde(with = "time::serde::iso8601")]
     pub created_at: OffsetDateTime,
 }
 
+/// The columns `TrackRecord` is read from. Queries list them rather than `SELECT *`,
+/// which would also fetch `tracks.data`, the audio of rows not yet moved to storage.
+const TRACK_COLUMNS: &[&str] = &[
+    "id",
+    "title",
+    "artist",
+    "album",
+    "album_id",
+    "album_artist",
+    "track_number",
+    "disc_number",
+    "year",
+    "genre",
+    "duration_ms",
+    "codec",
+    "bitrate",
+    "sample_rate",
+    "channels",
+    "artwork_id",
+    "loudness_lufs",
+    "true_peak_dbtp",
+    "track_gain_db",
+    "album_gain_db",
+    "filename",
+    "mime_type",
+    "size_bytes",
+    "content_hash",
+    "removed_at",
+    "created_at",
+];
+
+impl TrackRecord {
+    /// A select list of the record's columns from `table`, which may be an alias.
+    pub fn columns(table: &str) -> String {
+        TRACK_COLUMNS
+            .iter()
+            .map(|column| format!("{}.{}", table, column))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrackSort {
+    #[default]
+    Title,
+    Artist,
+    CreatedAt,
+    Duration,
+}
+
+impl TrackSort {
+    pub fn name(self) -> &'static str {
+        match self {
+            TrackSort::Title => "title",
+            TrackSort::Artist => "artist",
+            TrackSort::CreatedAt => "created_at",
+            TrackSort::Duration => "duration",
+        }
+    }
+
+    /// SQL for the sort key. Missing artists and durations sort as empty and zero.
+    pub fn expr(self) -> &'static str {
+        match self {
+            TrackSort::Title => "title",
+            TrackSort::Artist => "COALESCE(artist, '')",
+            TrackSort::CreatedAt => "created_at",
+            TrackSort::Duration => "COALESCE(duration_ms, 0)",
+        }
+    }
+
+    pub fn cursor(self, order: Order, track: &TrackRecord) -> Cursor {
+        let key = match self {
+            TrackSort::Title => SortKey::Text(track.title.clone()),
+            TrackSort::Artist => SortKey::Text(track.artist.clone().unwrap_or_default()),
+            TrackSort::CreatedAt => SortKey::time(track.created_at),
+            TrackSort::Duration => SortKey::Int(track.duration_ms.unwrap_or(0).into()),
+        };
+        Cursor::new(self.name(), order, key, track.id)
+    }
+}
+
+/// `GET /api/tracks` parameters. Text filters match whole values, ignoring case.
+#[derive(Debug, Default, Deserialize)]
+pub struct TrackListQuery {
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+    #[serde(default)]
+    pub sort: TrackSort,
+    #[serde(default)]
+    pub order: Order,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub genre: Option<String>,
+    pub year_from: Option<i32>,
+    pub year_to: Option<i32>,
+    pub mime_type: Option<String>,
+}
+
 #[derive(Debug, Deserialize, Validate)]
 pub struct RegisterPayload {
     #[validate(length(min = 3, message = "Username must be at least 3 characters"))]
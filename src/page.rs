@@ -0,0 +1,240 @@
+//! Cursor pagination shared by the listing endpoints.
+//!
+//! Pages are keyset based: the cursor carries the sort key and id of the last item
+//! returned, and the next page starts strictly after that pair. Unlike offsets, pages
+//! stay stable while tracks are added and cost the same however deep they are.
+
+use axum::http::StatusCode;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use sqlx::{Postgres, QueryBuilder};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+pub const DEFAULT_LIMIT: i64 = 50;
+pub const MAX_LIMIT: i64 = 500;
+
+/// The envelope every listing responds with.
+#[derive(Debug, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Pass back as `cursor` for the next page; absent on the last one.
+    pub next_cursor: Option<String>,
+    /// Items matching the filters, across all pages.
+    pub total: i64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Order {
+    #[default]
+    Asc,
+    Desc,
+}
+
+impl Order {
+    pub fn sql(self) -> &'static str {
+        match self {
+            Order::Asc => "ASC",
+            Order::Desc => "DESC",
+        }
+    }
+}
+
+/// A sort key value, typed so it binds back as the right SQL type.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SortKey {
+    Text(String),
+    Int(i64),
+    /// Microseconds since the epoch, Postgres' timestamp precision.
+    Time(i64),
+}
+
+impl SortKey {
+    pub fn time(t: OffsetDateTime) -> Self {
+        SortKey::Time((t.unix_timestamp_nanos() / 1000) as i64)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Cursor {
+    /// The sort the cursor was made for; it means nothing under another one.
+    sort: String,
+    order: Order,
+    key: SortKey,
+    id: Uuid,
+}
+
+impl Cursor {
+    pub fn new(sort: &str, order: Order, key: SortKey, id: Uuid) -> Self {
+        Self {
+            sort: sort.to_string(),
+            order,
+            key,
+            id,
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap_or_default())
+    }
+
+    /// Decodes a cursor from a request, which must use the same sort and order.
+    pub fn decode(cursor: &str, sort: &str, order: Order) -> Result<Self, StatusCode> {
+        URL_SAFE_NO_PAD
+            .decode(cursor)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<Cursor>(&bytes).ok())
+            .filter(|c| c.sort == sort && c.order == order)
+            .ok_or(StatusCode::BAD_REQUEST)
+    }
+
+    /// Appends `AND (expr, id) > (key, id)`, or `<` when descending.
+    pub fn push_after(&self, builder: &mut QueryBuilder<'_, Postgres>, expr: &str, id: &str) {
+        let op = match self.order {
+            Order::Asc => ">",
+            Order::Desc => "<",
+        };
+        builder.push(format!(" AND ({}, {}) {} (", expr, id, op));
+        match &self.key {
+            SortKey::Text(value) => builder.push_bind(value.clone()),
+            SortKey::Int(value) => builder.push_bind(*value),
+            SortKey::Time(micros) => builder.push_bind(
+                OffsetDateTime::from_unix_timestamp_nanos(i128::from(*micros) * 1000)
+                    .unwrap_or(OffsetDateTime::UNIX_EPOCH),
+            ),
+        };
+        builder.push(", ").push_bind(self.id).push(")");
+    }
+}
+
+/// The requested page size, capped at `MAX_LIMIT`.
+pub fn limit(requested: Option<i64>) -> Result<i64, StatusCode> {
+    match requested {
+        None => Ok(DEFAULT_LIMIT),
+        Some(limit) if limit < 1 => Err(StatusCode::BAD_REQUEST),
+        Some(limit) => Ok(limit.min(MAX_LIMIT)),
+    }
+}
+
+/// Builds the page from up to `limit + 1` rows; the extra row only says there's more.
+pub fn paginate<T>(
+    mut items: Vec<T>,
+    limit: i64,
+    total: i64,
+    cursor_for: impl Fn(&T) -> Cursor,
+) -> Page<T> {
+    let more = items.len() as i64 > limit;
+    items.truncate(limit as usize);
+    let next_cursor = more
+        .then(|| items.last().map(|last| cursor_for(last).encode()))
+        .flatten();
+    Page {
+        items,
+        next_cursor,
+        total,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::TrackRecord;
+
+    fn cursor(order: Order, key: SortKey) -> Cursor {
+        Cursor::new("title", order, key, Uuid::nil())
+    }
+
+    #[test]
+    fn cursors_round_trip_under_their_own_sort_only() {
+        let encoded = cursor(Order::Desc, SortKey::Text("Abc".into())).encode();
+
+        let decoded = Cursor::decode(&encoded, "title", Order::Desc).unwrap();
+        assert!(matches!(decoded.key, SortKey::Text(ref t) if t == "Abc"));
+        assert_eq!(decoded.id, Uuid::nil());
+
+        assert_eq!(
+            Cursor::decode(&encoded, "artist", Order::Desc).unwrap_err(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            Cursor::decode(&encoded, "title", Order::Asc).unwrap_err(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            Cursor::decode("not a cursor!", "title", Order::Desc).unwrap_err(),
+            StatusCode::BAD_REQUEST
+        );
+        let garbage = URL_SAFE_NO_PAD.encode(b"{\"sort\":\"title\"}");
+        assert!(Cursor::decode(&garbage, "title", Order::Desc).is_err());
+    }
+
+    #[test]
+    fn push_after_compares_the_key_and_id_pair() {
+        let select = format!(
+            "SELECT {} FROM tracks WHERE removed_at IS NULL",
+            TrackRecord::columns("tracks")
+        );
+
+        let mut builder = QueryBuilder::new(select.clone());
+        cursor(Order::Asc, SortKey::Int(5)).push_after(
+            &mut builder,
+            "COALESCE(duration_ms, 0)",
+            "id",
+        );
+        assert_eq!(
+            builder.sql(),
+            format!("{} AND (COALESCE(duration_ms, 0), id) > ($1, $2)", select)
+        );
+
+        let mut builder = QueryBuilder::new(select.clone());
+        cursor(Order::Desc, SortKey::time(OffsetDateTime::UNIX_EPOCH)).push_after(
+            &mut builder,
+            "created_at",
+            "id",
+        );
+        assert_eq!(
+            builder.sql(),
+            format!("{} AND (created_at, id) < ($1, $2)", select)
+        );
+        assert!(!builder.sql().contains('*'));
+    }
+
+    #[test]
+    fn time_keys_keep_microseconds() {
+        let t = OffsetDateTime::from_unix_timestamp_nanos(1_700_000_000_123_456_789).unwrap();
+        assert!(matches!(
+            SortKey::time(t),
+            SortKey::Time(1_700_000_000_123_456)
+        ));
+    }
+
+    #[test]
+    fn paginate_only_links_a_next_page_when_there_is_one() {
+        let cursor_for = |n: &i64| cursor(Order::Asc, SortKey::Int(*n));
+
+        let page = paginate(vec![1, 2, 3], 2, 3, cursor_for);
+        assert_eq!(page.items, vec![1, 2]);
+        assert_eq!(page.total, 3);
+        let next = Cursor::decode(&page.next_cursor.unwrap(), "title", Order::Asc).unwrap();
+        assert!(matches!(next.key, SortKey::Int(2)));
+
+        let page = paginate(vec![1, 2], 2, 2, cursor_for);
+        assert_eq!(page.items, vec![1, 2]);
+        assert!(page.next_cursor.is_none());
+
+        let page = paginate(Vec::new(), 2, 0, cursor_for);
+        assert!(page.items.is_empty());
+        assert!(page.next_cursor.is_none());
+    }
+
+    #[test]
+    fn limit_defaults_caps_and_rejects_non_positive() {
+        assert_eq!(limit(None), Ok(DEFAULT_LIMIT));
+        assert_eq!(limit(Some(10)), Ok(10));
+        assert_eq!(limit(Some(MAX_LIMIT + 1)), Ok(MAX_LIMIT));
+        assert_eq!(limit(Some(0)), Err(StatusCode::BAD_REQUEST));
+        assert_eq!(limit(Some(-1)), Err(StatusCode::BAD_REQUEST));
+    }
+}
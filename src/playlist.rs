@@ -1,17 +1,30 @@
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    response::{IntoResponse, Response},
-    Json,
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::Response,
+    routing::{delete, get, post},
+    Extension, Json, Router,
 };
 use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgExecutor, PgPool, QueryBuilder};
+use std::collections::HashSet;
 use std::sync::Arc;
 use time::OffsetDateTime;
 use uuid::Uuid;
+use validator::Validate;
 
+use crate::auth::{self, Claims};
+use crate::cache;
 use crate::models::TrackRecord;
+use crate::page::{self, Cursor, Order, SortKey};
 use crate::AppState;
 
+/// Space between neighbouring `order_index` values, so a track can be moved between
+/// two others without renumbering the rest.
+const ORDER_GAP: i64 = 1024;
+/// Most tracks or entries one batch request can name.
+const MAX_BATCH: usize = 1000;
+
 #[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
 pub struct Playlist {
     pub id: Uuid,
@@ -20,50 +33,313 @@ pub struct Playlist {
     pub description: Option<String>,
     #[serde(with = "time::serde::iso8601")]
     pub created_at: OffsetDateTime,
+    /// Last time the details or entries changed.
+    #[serde(with = "time::serde::iso8601")]
+    pub updated_at: OffsetDateTime,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PlaylistWithTracks {
     #[serde(flatten)]
     pub playlist: Playlist,
-    pub tracks: Vec<TrackRecord>,
+    pub tracks: Vec<PlaylistEntry>,
+    /// Sum of the tracks' durations; tracks of unknown length count as zero.
+    pub total_duration_ms: i64,
 }
 
-#[derive(Deserialize)]
+/// A track's place in a playlist. The same track can have several.
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct PlaylistEntry {
+    pub entry_id: Uuid,
+    #[serde(with = "time::serde::iso8601")]
+    pub added_at: OffsetDateTime,
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub track: TrackRecord,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AddedEntry {
+    pub entry_id: Uuid,
+}
+
+#[derive(Deserialize, Validate)]
 pub struct CreatePlaylistPayload {
+    #[validate(length(min = 1, max = 100, message = "Name must be 1 to 100 characters"))]
     pub name: String,
+    #[validate(length(max = 1000, message = "Description must be at most 1000 characters"))]
+    pub description: Option<String>,
+}
+
+impl CreatePlaylistPayload {
+    fn trim(self) -> Self {
+        Self {
+            name: self.name.trim().to_string(),
+            description: self
+                .description
+                .map(|d| d.trim().to_string())
+                .filter(|d| !d.is_empty()),
+        }
+    }
+}
+
+/// `PATCH /api/playlists/:id`. Missing fields are left alone; an empty description
+/// removes it.
+#[derive(Deserialize, Validate)]
+pub struct UpdatePlaylistPayload {
+    #[validate(length(min = 1, max = 100, message = "Name must be 1 to 100 characters"))]
+    pub name: Option<String>,
+    #[validate(length(max = 1000, message = "Description must be at most 1000 characters"))]
     pub description: Option<String>,
 }
 
+impl UpdatePlaylistPayload {
+    fn trim(self) -> Self {
+        Self {
+            name: self.name.map(|n| n.trim().to_string()),
+            description: self.description.map(|d| d.trim().to_string()),
+        }
+    }
+}
+
 #[derive(Deserialize)]
 pub struct AddTrackPayload {
     pub track_id: Uuid,
 }
 
-pub async fn list_playlists(
-    State(state): State<Arc<AppState>>,
-) -> Result<Json<Vec<Playlist>>, StatusCode> {
-    // TODO: Filter by user_id from auth token. For now, list all.
-    let playlists =
-        sqlx::query_as::<_, Playlist>("SELECT * FROM playlists ORDER BY created_at DESC")
-            .fetch_all(&state.app.db)
+/// `POST /api/playlists/:id/tracks/batch`. Tracks go in the order given: `track_ids`,
+/// then the album's tracks, then the artist's.
+#[derive(Deserialize)]
+pub struct BatchAddPayload {
+    #[serde(default)]
+    pub track_ids: Vec<Uuid>,
+    /// Every track of the album, in disc and track order.
+    pub album_id: Option<Uuid>,
+    /// Every track the artist is credited on, album by album.
+    pub artist_id: Option<Uuid>,
+    /// Where the first new entry goes, counting from 0. Defaults to the end.
+    pub position: Option<usize>,
+}
+
+/// `DELETE /api/playlists/:id/tracks/batch`. A track id removes every entry of it.
+#[derive(Deserialize)]
+pub struct BatchRemovePayload {
+    #[serde(default)]
+    pub track_ids: Vec<Uuid>,
+    #[serde(default)]
+    pub entry_ids: Vec<Uuid>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchStatus {
+    Added,
+    Removed,
+    /// No such track, or for removals, nothing of it in the playlist.
+    NotFound,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchItem {
+    /// The track or entry id as given in the request.
+    pub id: Uuid,
+    pub status: BatchStatus,
+    /// The entry an added track got.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entry_id: Option<Uuid>,
+}
+
+/// What a batch did, item by item in request order.
+#[derive(Debug, Serialize)]
+pub struct BatchResult {
+    pub succeeded: usize,
+    pub failed: usize,
+    pub results: Vec<BatchItem>,
+}
+
+impl BatchResult {
+    fn new(results: Vec<BatchItem>) -> Self {
+        let failed = results
+            .iter()
+            .filter(|item| item.status == BatchStatus::NotFound)
+            .count();
+        Self {
+            succeeded: results.len() - failed,
+            failed,
+            results,
+        }
+    }
+}
+
+/// `PATCH /api/playlists/:id/tracks`: move one entry, or set the whole order.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum ReorderPayload {
+    /// Moves the entry at position `from` to position `to`, counting from 0. With
+    /// `entry_id`, nothing moves unless that is the entry at `from`.
+    Move {
+        from: usize,
+        to: usize,
+        entry_id: Option<Uuid>,
+    },
+    /// Every entry in the playlist, in their new order.
+    Order { entry_ids: Vec<Uuid> },
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlaylistSort {
+    Name,
+    #[default]
+    CreatedAt,
+}
+
+impl PlaylistSort {
+    fn name(self) -> &'static str {
+        match self {
+            PlaylistSort::Name => "name",
+            PlaylistSort::CreatedAt => "created_at",
+        }
+    }
+
+    fn cursor(self, order: Order, playlist: &Playlist) -> Cursor {
+        let key = match self {
+            PlaylistSort::Name => SortKey::Text(playlist.name.clone()),
+            PlaylistSort::CreatedAt => SortKey::time(playlist.created_at),
+        };
+        Cursor::new(self.name(), order, key, playlist.id)
+    }
+}
+
+/// `GET /api/playlists` parameters. Newest first unless asked otherwise.
+#[derive(Deserialize)]
+pub struct PlaylistListQuery {
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+    #[serde(default)]
+    pub sort: PlaylistSort,
+    pub order: Option<Order>,
+}
+
+/// Every playlist route, all behind `auth_middleware`.
+pub fn routes(state: Arc<AppState>) -> Router<Arc<AppState>> {
+    let auth = axum::middleware::from_fn_with_state(state, auth::auth_middleware);
+    Router::new()
+        .route("/api/playlists", get(list_playlists).post(create_playlist))
+        .route(
+            "/api/playlists/:id",
+            get(get_playlist)
+                .patch(update_playlist)
+                .delete(delete_playlist),
+        )
+        .route(
+            "/api/playlists/:id/tracks",
+            post(add_track_to_playlist).patch(reorder_tracks),
+        )
+        .route(
+            "/api/playlists/:id/tracks/batch",
+            post(add_tracks_to_playlist).delete(remove_tracks_from_playlist),
+        )
+        .route(
+            "/api/playlists/:id/tracks/:track_id",
+            delete(remove_track_from_playlist),
+        )
+        .route(
+            "/api/playlists/:id/entries/:entry_id",
+            delete(remove_entry_from_playlist),
+        )
+        .route_layer(auth)
+}
+
+/// The playlist, if `user_id` owns it: 404 if there's no such playlist, 403 if it's
+/// someone else's. Playlists from before owners were recorded belong to no one.
+async fn owned_playlist(db: &PgPool, id: Uuid, user_id: Uuid) -> Result<Playlist, StatusCode> {
+    let playlist =
+        sqlx::query_as::<sqlx::Postgres, Playlist>("SELECT * FROM playlists WHERE id = $1")
+            .bind(id)
+            .fetch_optional(db)
             .await
             .map_err(|e| {
-                eprintln!("Error listing playlists: {}", e);
+                eprintln!("Error fetching playlist: {}", e);
                 StatusCode::INTERNAL_SERVER_ERROR
-            })?;
+            })?
+            .ok_or(StatusCode::NOT_FOUND)?;
+    if playlist.user_id != Some(user_id) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(playlist)
+}
+
+pub async fn list_playlists(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<PlaylistListQuery>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let limit = page::limit(query.limit)?;
+    let sort = query.sort;
+    let order = query.order.unwrap_or(match sort {
+        PlaylistSort::Name => Order::Asc,
+        PlaylistSort::CreatedAt => Order::Desc,
+    });
+    let cursor = query
+        .cursor
+        .as_deref()
+        .map(|c| Cursor::decode(c, sort.name(), order))
+        .transpose()?;
+    let user_id = auth::user_id(&state.app.db, &claims).await?;
+    let list_error = |e: sqlx::Error| {
+        eprintln!("Error listing playlists: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
 
-    Ok(Json(playlists))
+    let total = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM playlists WHERE user_id = $1"#,
+        user_id
+    )
+    .fetch_one(&state.app.db)
+    .await
+    .map_err(list_error)?;
+
+    let mut select = QueryBuilder::new("SELECT * FROM playlists WHERE user_id = ");
+    select.push_bind(user_id);
+    if let Some(cursor) = &cursor {
+        cursor.push_after(&mut select, sort.name(), "id");
+    }
+    select
+        .push(format!(
+            " ORDER BY {} {}, id {} LIMIT ",
+            sort.name(),
+            order.sql(),
+            order.sql()
+        ))
+        .push_bind(limit + 1);
+    let playlists = select
+        .build_query_as::<Playlist>()
+        .fetch_all(&state.app.db)
+        .await
+        .map_err(list_error)?;
+
+    let page = page::paginate(playlists, limit, total, |playlist| {
+        sort.cursor(order, playlist)
+    });
+    Ok(cache::json(&headers, &page))
 }
 
 pub async fn create_playlist(
     State(state): State<Arc<AppState>>,
-    // TODO: Extract user_id from Claims
+    Extension(claims): Extension<Claims>,
     Json(payload): Json<CreatePlaylistPayload>,
 ) -> Result<Json<Playlist>, StatusCode> {
+    let payload = payload.trim();
+    if payload.validate().is_err() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let user_id = auth::user_id(&state.app.db, &claims).await?;
     let playlist = sqlx::query_as::<_, Playlist>(
-        "INSERT INTO playlists (name, description) VALUES ($1, $2) RETURNING *",
+        "INSERT INTO playlists (user_id, name, description) VALUES ($1, $2, $3) RETURNING *",
     )
+    .bind(user_id)
     .bind(payload.name)
     .bind(payload.description)
     .fetch_one(&state.app.db)
@@ -75,10 +351,54 @@ pub async fn create_playlist(
 
     Ok(Json(playlist))
 }
+
+pub async fn update_playlist(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<UpdatePlaylistPayload>,
+) -> Result<Json<Playlist>, StatusCode> {
+    let payload = payload.trim();
+    if payload.validate().is_err() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let user_id = auth::user_id(&state.app.db, &claims).await?;
+    let playlist = owned_playlist(&state.app.db, id, user_id).await?;
+    if payload.name.is_none() && payload.description.is_none() {
+        return Ok(Json(playlist));
+    }
+
+    let playlist = sqlx::query_as::<_, Playlist>(
+        r#"
+        UPDATE playlists
+        SET name = COALESCE($2, name),
+            description = CASE WHEN $3::TEXT IS NULL THEN description ELSE NULLIF($3, '') END,
+            updated_at = NOW()
+        WHERE id = $1
+        RETURNING *
+        "#,
+    )
+    .bind(id)
+    .bind(payload.name)
+    .bind(payload.description)
+    .fetch_one(&state.app.db)
+    .await
+    .map_err(|e| {
+        eprintln!("Error updating playlist: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(playlist))
+}
+
 pub async fn delete_playlist(
     State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
     Path(id): Path<Uuid>,
 ) -> Result<StatusCode, StatusCode> {
+    let user_id = auth::user_id(&state.app.db, &claims).await?;
+    owned_playlist(&state.app.db, id, user_id).await?;
+
     sqlx::query!("DELETE FROM playlists WHERE id = $1", id)
         .execute(&state.app.db)
         .await
@@ -89,26 +409,23 @@ pub async fn delete_playlist(
 
 pub async fn get_playlist(
     State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
     Path(id): Path<Uuid>,
-) -> Result<Json<PlaylistWithTracks>, StatusCode> {
-    let playlist = sqlx::query_as::<sqlx::Postgres, Playlist>("SELECT * FROM playlists WHERE id = $1")
-        .bind(id)
-        .fetch_optional(&state.app.db)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-        .ok_or(StatusCode::NOT_FOUND)?;
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let user_id = auth::user_id(&state.app.db, &claims).await?;
+    let playlist = owned_playlist(&state.app.db, id, user_id).await?;
 
-    // Fetch tracks
-    // Join playlist_tracks with tracks
-    let tracks = sqlx::query_as::<sqlx::Postgres, TrackRecord>(
+    let tracks = sqlx::query_as::<sqlx::Postgres, PlaylistEntry>(&format!(
         r#"
-        SELECT t.* 
+        SELECT {}, pt.id AS entry_id, pt.added_at
         FROM tracks t
         JOIN playlist_tracks pt ON t.id = pt.track_id
         WHERE pt.playlist_id = $1
-        ORDER BY pt.order_index ASC, pt.added_at ASC
+        ORDER BY pt.order_index ASC, pt.added_at ASC, pt.id ASC
         "#,
-    )
+        TrackRecord::columns("t")
+    ))
     .bind(id)
     .fetch_all(&state.app.db)
     .await
@@ -117,49 +434,1222 @@ pub async fn get_playlist(
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
 
-    Ok(Json(PlaylistWithTracks { playlist, tracks }))
+    let total_duration_ms = tracks
+        .iter()
+        .filter_map(|entry| entry.track.duration_ms)
+        .map(i64::from)
+        .sum();
+
+    Ok(cache::json(
+        &headers,
+        &PlaylistWithTracks {
+            playlist,
+            tracks,
+            total_duration_ms,
+        },
+    ))
 }
 
 pub async fn add_track_to_playlist(
     State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
     Path(id): Path<Uuid>,
     Json(payload): Json<AddTrackPayload>,
-) -> Result<StatusCode, StatusCode> {
-    // Check if exists
-    let _exists = sqlx::query!("SELECT id FROM playlists WHERE id = $1", id)
-        .fetch_optional(&state.app.db)
+) -> Result<Json<AddedEntry>, StatusCode> {
+    let user_id = auth::user_id(&state.app.db, &claims).await?;
+    owned_playlist(&state.app.db, id, user_id).await?;
+
+    let add_error = |e: sqlx::Error| {
+        eprintln!("Error adding track to playlist: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+
+    // Add track, at the end. A track already in the playlist gets another entry.
+    let mut tx = state.app.db.begin().await.map_err(add_error)?;
+    // Takes turns with reorders and batch adds, which work from the same positions
+    sqlx::query!("SELECT id FROM playlists WHERE id = $1 FOR UPDATE", id)
+        .fetch_optional(&mut *tx)
         .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_err(add_error)?
         .ok_or(StatusCode::NOT_FOUND)?;
-
-    // Add track
-    sqlx::query!(
-        "INSERT INTO playlist_tracks (playlist_id, track_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+    let entry_id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO playlist_tracks (playlist_id, track_id, order_index)
+        SELECT $1, $2, COALESCE(MAX(order_index) + $3, 0)
+        FROM playlist_tracks
+        WHERE playlist_id = $1
+        RETURNING id
+        "#,
         id,
-        payload.track_id
+        payload.track_id,
+        ORDER_GAP
     )
-    .execute(&state.app.db)
+    .fetch_one(&mut *tx)
     .await
-    .map_err(|e| {
-        eprintln!("Error adding track to playlist: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
+    .map_err(|e| match e {
+        sqlx::Error::Database(e) if e.is_foreign_key_violation() => StatusCode::NOT_FOUND,
+        e => add_error(e),
     })?;
+    touch(&mut *tx, id).await?;
+    tx.commit().await.map_err(add_error)?;
 
-    Ok(StatusCode::OK)
+    Ok(Json(AddedEntry { entry_id }))
 }
 
+/// Removes every entry of the track from the playlist.
 pub async fn remove_track_from_playlist(
     State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
     Path((playlist_id, track_id)): Path<(Uuid, Uuid)>,
 ) -> Result<StatusCode, StatusCode> {
+    let user_id = auth::user_id(&state.app.db, &claims).await?;
+    owned_playlist(&state.app.db, playlist_id, user_id).await?;
+
+    let mut tx = state
+        .app
+        .db
+        .begin()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
     sqlx::query!(
         "DELETE FROM playlist_tracks WHERE playlist_id = $1 AND track_id = $2",
         playlist_id,
         track_id
     )
-    .execute(&state.app.db)
+    .execute(&mut *tx)
     .await
     .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    touch(&mut *tx, playlist_id).await?;
+    tx.commit()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(StatusCode::OK)
+}
+
+pub async fn remove_entry_from_playlist(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Path((playlist_id, entry_id)): Path<(Uuid, Uuid)>,
+) -> Result<StatusCode, StatusCode> {
+    let user_id = auth::user_id(&state.app.db, &claims).await?;
+    owned_playlist(&state.app.db, playlist_id, user_id).await?;
+
+    let remove_error = |e: sqlx::Error| {
+        eprintln!("Error removing playlist entry: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+
+    let mut tx = state.app.db.begin().await.map_err(remove_error)?;
+    let deleted = sqlx::query!(
+        "DELETE FROM playlist_tracks WHERE playlist_id = $1 AND id = $2",
+        playlist_id,
+        entry_id
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(remove_error)?
+    .rows_affected();
+    if deleted == 0 {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    touch(&mut *tx, playlist_id).await?;
+    tx.commit().await.map_err(remove_error)?;
+
+    Ok(StatusCode::OK)
+}
+
+/// Bumps `updated_at` after the playlist's entries change.
+async fn touch<'e>(db: impl PgExecutor<'e>, id: Uuid) -> Result<(), StatusCode> {
+    sqlx::query!("UPDATE playlists SET updated_at = NOW() WHERE id = $1", id)
+        .execute(db)
+        .await
+        .map_err(|e| {
+            eprintln!("Error updating playlist: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(())
+}
+
+/// An `order_index` strictly between two neighbours, either of which may be missing at
+/// the ends of the playlist. `None` when they're adjacent and the playlist needs
+/// renumbering.
+fn order_between(prev: Option<i64>, next: Option<i64>) -> Option<i64> {
+    orders_between(prev, next, 1).map(|orders| orders[0])
+}
+
+/// `count` increasing `order_index` values between two neighbours, spread evenly when
+/// they're squeezed in, `ORDER_GAP` apart at the ends.
+fn orders_between(prev: Option<i64>, next: Option<i64>, count: usize) -> Option<Vec<i64>> {
+    let count = i64::try_from(count).ok()?;
+    let (start, step) = match (prev, next) {
+        (Some(prev), Some(next)) => {
+            let step = (next - prev) / (count + 1);
+            if step < 1 {
+                return None;
+            }
+            (prev, step)
+        }
+        (Some(prev), None) => (prev, ORDER_GAP),
+        (None, Some(next)) => (
+            next.checked_sub(ORDER_GAP.checked_mul(count + 1)?)?,
+            ORDER_GAP,
+        ),
+        (None, None) => (-ORDER_GAP, ORDER_GAP),
+    };
+    (1..=count)
+        .map(|i| start.checked_add(step.checked_mul(i)?))
+        .collect()
+}
+
+/// Spaces the playlist's entries `ORDER_GAP` apart, in the given order.
+async fn renumber(conn: &mut PgConnection, id: Uuid, order: &[Uuid]) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE playlist_tracks pt
+        SET order_index = (o.position - 1) * $3
+        FROM unnest($2::uuid[]) WITH ORDINALITY AS o(id, position)
+        WHERE pt.playlist_id = $1 AND pt.id = o.id
+        "#,
+        id,
+        order,
+        ORDER_GAP
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
 
+/// `PATCH /api/playlists/:id/tracks`. A move rewrites just the moved entry, unless
+/// its new neighbours have run out of room between them.
+///
+/// Reorders of the same playlist take turns, each working from the order the last one
+/// left. A client working from an order that has since changed gets 409 Conflict
+/// rather than a shuffle it didn't ask for: when the entry at `from` isn't the
+/// `entry_id` it expected, or when its full order isn't the playlist's entries.
+pub async fn reorder_tracks(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<ReorderPayload>,
+) -> Result<StatusCode, StatusCode> {
+    let user_id = auth::user_id(&state.app.db, &claims).await?;
+    owned_playlist(&state.app.db, id, user_id).await?;
+    let reorder_error = |e: sqlx::Error| {
+        eprintln!("Error reordering playlist: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+
+    let mut tx = state.app.db.begin().await.map_err(reorder_error)?;
+    sqlx::query!("SELECT id FROM playlists WHERE id = $1 FOR UPDATE", id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(reorder_error)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let entries = sqlx::query!(
+        r#"
+        SELECT id, order_index
+        FROM playlist_tracks
+        WHERE playlist_id = $1
+        ORDER BY order_index, added_at, id
+        "#,
+        id
+    )
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(reorder_error)?;
+    let mut order: Vec<Uuid> = entries.iter().map(|e| e.id).collect();
+
+    match payload {
+        ReorderPayload::Move { from, to, entry_id } => {
+            if from >= order.len() || to >= order.len() {
+                return Err(StatusCode::BAD_REQUEST);
+            }
+            if entry_id.is_some_and(|entry_id| entry_id != order[from]) {
+                return Err(StatusCode::CONFLICT);
+            }
+            let moved = order.remove(from);
+            let mut indices: Vec<i64> = entries.iter().map(|e| e.order_index).collect();
+            indices.remove(from);
+            let prev = to.checked_sub(1).map(|i| indices[i]);
+            match order_between(prev, indices.get(to).copied()) {
+                Some(index) => {
+                    sqlx::query!(
+                        "UPDATE playlist_tracks SET order_index = $3
+                         WHERE playlist_id = $1 AND id = $2",
+                        id,
+                        moved,
+                        index
+                    )
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(reorder_error)?;
+                }
+                None => {
+                    order.insert(to, moved);
+                    renumber(&mut tx, id, &order).await.map_err(reorder_error)?;
+                }
+            }
+        }
+        ReorderPayload::Order { entry_ids } => {
+            let mut wanted = entry_ids.clone();
+            wanted.sort();
+            order.sort();
+            if wanted != order {
+                return Err(StatusCode::CONFLICT);
+            }
+            renumber(&mut tx, id, &entry_ids)
+                .await
+                .map_err(reorder_error)?;
+        }
+    }
+
+    touch(&mut *tx, id).await?;
+    tx.commit().await.map_err(reorder_error)?;
     Ok(StatusCode::OK)
 }
+
+/// The tracks a batch add names, in order, each with whether it exists.
+async fn batch_tracks(
+    conn: &mut PgConnection,
+    payload: &BatchAddPayload,
+) -> Result<Vec<(Uuid, bool)>, StatusCode> {
+    let batch_error = |e: sqlx::Error| {
+        eprintln!("Error resolving batch tracks: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+    let known: HashSet<Uuid> = sqlx::query_scalar!(
+        "SELECT id FROM tracks WHERE id = ANY($1) AND removed_at IS NULL",
+        &payload.track_ids
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(batch_error)?
+    .into_iter()
+    .collect();
+    let mut tracks: Vec<(Uuid, bool)> = payload
+        .track_ids
+        .iter()
+        .map(|id| (*id, known.contains(id)))
+        .collect();
+
+    if let Some(album_id) = payload.album_id {
+        sqlx::query_scalar!("SELECT id FROM albums WHERE id = $1", album_id)
+            .fetch_optional(&mut *conn)
+            .await
+            .map_err(batch_error)?
+            .ok_or(StatusCode::NOT_FOUND)?;
+        let album = sqlx::query_scalar!(
+            r#"
+            SELECT id FROM tracks
+            WHERE album_id = $1 AND removed_at IS NULL
+            ORDER BY disc_number NULLS FIRST, track_number NULLS LAST, title
+            "#,
+            album_id
+        )
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(batch_error)?;
+        tracks.extend(album.into_iter().map(|id| (id, true)));
+    }
+
+    if let Some(artist_id) = payload.artist_id {
+        sqlx::query_scalar!("SELECT id FROM artists WHERE id = $1", artist_id)
+            .fetch_optional(&mut *conn)
+            .await
+            .map_err(batch_error)?
+            .ok_or(StatusCode::NOT_FOUND)?;
+        let artist = sqlx::query_scalar!(
+            r#"
+            SELECT t.id
+            FROM tracks t
+            JOIN track_artists ta ON ta.track_id = t.id
+            LEFT JOIN albums al ON al.id = t.album_id
+            WHERE ta.artist_id = $1 AND t.removed_at IS NULL
+            ORDER BY al.year NULLS LAST, al.normalized_title NULLS LAST,
+                     t.disc_number NULLS FIRST, t.track_number NULLS LAST, t.title
+            "#,
+            artist_id
+        )
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(batch_error)?;
+        tracks.extend(artist.into_iter().map(|id| (id, true)));
+    }
+
+    if tracks.len() > MAX_BATCH {
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+    Ok(tracks)
+}
+
+/// `POST /api/playlists/:id/tracks/batch`. Every track that exists is added, in one
+/// transaction; the rest are reported as `not_found`.
+pub async fn add_tracks_to_playlist(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<BatchAddPayload>,
+) -> Result<Json<BatchResult>, StatusCode> {
+    if payload.track_ids.len() > MAX_BATCH {
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+    let user_id = auth::user_id(&state.app.db, &claims).await?;
+    owned_playlist(&state.app.db, id, user_id).await?;
+    let add_error = |e: sqlx::Error| {
+        eprintln!("Error adding tracks to playlist: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+
+    let mut tx = state.app.db.begin().await.map_err(add_error)?;
+    // Takes turns with reorders, which work from the same positions
+    sqlx::query!("SELECT id FROM playlists WHERE id = $1 FOR UPDATE", id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(add_error)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let tracks = batch_tracks(&mut tx, &payload).await?;
+    let entries = sqlx::query!(
+        r#"
+        SELECT id, order_index
+        FROM playlist_tracks
+        WHERE playlist_id = $1
+        ORDER BY order_index, added_at, id
+        "#,
+        id
+    )
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(add_error)?;
+    let position = payload.position.unwrap_or(entries.len());
+    if position > entries.len() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let adding: Vec<Uuid> = tracks
+        .iter()
+        .filter(|(_, exists)| *exists)
+        .map(|(track_id, _)| *track_id)
+        .collect();
+    let prev = position.checked_sub(1).map(|i| entries[i].order_index);
+    let next = entries.get(position).map(|e| e.order_index);
+    let (orders, renumbered) = match orders_between(prev, next, adding.len()) {
+        Some(orders) => (orders, false),
+        // Out of room: any distinct values do, the renumbering below puts them in place
+        None => ((0..adding.len() as i64).collect(), true),
+    };
+    let added = sqlx::query!(
+        r#"
+        INSERT INTO playlist_tracks (playlist_id, track_id, order_index)
+        SELECT $1, track_id, order_index
+        FROM unnest($2::uuid[], $3::bigint[]) AS new(track_id, order_index)
+        RETURNING id, order_index
+        "#,
+        id,
+        &adding,
+        &orders
+    )
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(add_error)?;
+    // The new orders increase along the tracks, so sorting by them gives the entries in
+    // the order their tracks were given
+    let mut added: Vec<(i64, Uuid)> = added.into_iter().map(|e| (e.order_index, e.id)).collect();
+    added.sort_unstable();
+    let entry_ids: Vec<Uuid> = added.into_iter().map(|(_, entry_id)| entry_id).collect();
+
+    if renumbered {
+        let order: Vec<Uuid> = entries[..position]
+            .iter()
+            .map(|e| e.id)
+            .chain(entry_ids.iter().copied())
+            .chain(entries[position..].iter().map(|e| e.id))
+            .collect();
+        renumber(&mut tx, id, &order).await.map_err(add_error)?;
+    }
+    if !adding.is_empty() {
+        touch(&mut *tx, id).await?;
+    }
+    tx.commit().await.map_err(add_error)?;
+
+    let mut entry_ids = entry_ids.into_iter();
+    let results = tracks
+        .into_iter()
+        .map(|(track_id, exists)| BatchItem {
+            id: track_id,
+            status: if exists {
+                BatchStatus::Added
+            } else {
+                BatchStatus::NotFound
+            },
+            entry_id: exists.then(|| entry_ids.next()).flatten(),
+        })
+        .collect();
+    Ok(Json(BatchResult::new(results)))
+}
+
+/// `DELETE /api/playlists/:id/tracks/batch`, in one transaction.
+pub async fn remove_tracks_from_playlist(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<BatchRemovePayload>,
+) -> Result<Json<BatchResult>, StatusCode> {
+    if payload.track_ids.len() + payload.entry_ids.len() > MAX_BATCH {
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+    let user_id = auth::user_id(&state.app.db, &claims).await?;
+    owned_playlist(&state.app.db, id, user_id).await?;
+    let remove_error = |e: sqlx::Error| {
+        eprintln!("Error removing tracks from playlist: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+
+    let mut tx = state.app.db.begin().await.map_err(remove_error)?;
+    let removed_tracks = sqlx::query_scalar!(
+        "DELETE FROM playlist_tracks WHERE playlist_id = $1 AND track_id = ANY($2)
+         RETURNING track_id",
+        id,
+        &payload.track_ids
+    )
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(remove_error)?;
+    let removed_entries = sqlx::query_scalar!(
+        "DELETE FROM playlist_tracks WHERE playlist_id = $1 AND id = ANY($2) RETURNING id",
+        id,
+        &payload.entry_ids
+    )
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(remove_error)?;
+    if !removed_tracks.is_empty() || !removed_entries.is_empty() {
+        touch(&mut *tx, id).await?;
+    }
+    tx.commit().await.map_err(remove_error)?;
+
+    let item = |id: &Uuid, removed: &[Uuid]| BatchItem {
+        id: *id,
+        status: if removed.contains(id) {
+            BatchStatus::Removed
+        } else {
+            BatchStatus::NotFound
+        },
+        entry_id: None,
+    };
+    let results = payload
+        .track_ids
+        .iter()
+        .map(|track_id| item(track_id, &removed_tracks))
+        .chain(
+            payload
+                .entry_ids
+                .iter()
+                .map(|entry_id| item(entry_id, &removed_entries)),
+        )
+        .collect();
+    Ok(Json(BatchResult::new(results)))
+}
+
+/// The database tests each get a freshly migrated database of their own, made next to
+/// the one at `DATABASE_URL` and dropped afterwards. Run them with
+/// `cargo test -- --ignored`.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::App;
+    use crate::storage::LocalStorage;
+    use crate::transcode_cache::TranscodeCache;
+    use axum::body::Body;
+    use axum::http::{header, Method, Request};
+    use jsonwebtoken::{encode, EncodingKey};
+    use std::sync::Once;
+    use tower::ServiceExt;
+
+    const SECRET: &str = "playlist-tests";
+
+    struct TestApp {
+        router: Router,
+        db: PgPool,
+        scratch: std::path::PathBuf,
+    }
+
+    impl TestApp {
+        fn new(db: PgPool) -> Self {
+            static SECRET_SET: Once = Once::new();
+            SECRET_SET.call_once(|| std::env::set_var("JWT_SECRET", SECRET));
+
+            let scratch = std::env::temp_dir().join(format!("arcsin-test-{}", Uuid::new_v4()));
+            let app = App::new(
+                db.clone(),
+                Arc::new(LocalStorage::new(scratch.join("storage"))),
+                TranscodeCache::open(scratch.join("transcodes"), 1024 * 1024).unwrap(),
+            );
+            let state = Arc::new(AppState { app });
+            let auth = axum::middleware::from_fn_with_state(state.clone(), auth::auth_middleware);
+            let router = routes(state.clone())
+                .route("/api/search", get(crate::search::search).route_layer(auth))
+                .with_state(state);
+            Self {
+                router,
+                db,
+                scratch,
+            }
+        }
+
+        /// A new user, and a token for them.
+        async fn user(&self) -> (Uuid, String) {
+            let username = format!("test-{}", Uuid::new_v4());
+            let id = sqlx::query_scalar!(
+                "INSERT INTO users (username, password_hash) VALUES ($1, '') RETURNING id",
+                username
+            )
+            .fetch_one(&self.db)
+            .await
+            .unwrap();
+            let now = OffsetDateTime::now_utc().unix_timestamp() as usize;
+            let claims = Claims {
+                sub: username,
+                exp: now + 3600,
+                iat: now,
+            };
+            let token = encode(
+                &jsonwebtoken::Header::default(),
+                &claims,
+                &EncodingKey::from_secret(SECRET.as_bytes()),
+            )
+            .unwrap();
+            (id, token)
+        }
+
+        async fn track(&self) -> Uuid {
+            let hash = Uuid::new_v4().to_string();
+            sqlx::query_scalar!(
+                "INSERT INTO tracks (title, filename, content_hash, size_bytes)
+                 VALUES ('Test', 'test.mp3', $1, 0) RETURNING id",
+                hash
+            )
+            .fetch_one(&self.db)
+            .await
+            .unwrap()
+        }
+
+        /// An album by a new artist, with `count` tracks in album order. They're stored
+        /// last track first, so that album order has to come from the track numbers.
+        async fn album(&self, count: i32) -> (Uuid, Uuid, Vec<Uuid>) {
+            let name = format!("Test {}", Uuid::new_v4());
+            let artist = sqlx::query_scalar!(
+                "INSERT INTO artists (name, normalized_name) VALUES ($1, $1) RETURNING id",
+                name
+            )
+            .fetch_one(&self.db)
+            .await
+            .unwrap();
+            let album = sqlx::query_scalar!(
+                "INSERT INTO albums (title, normalized_title, artist_id)
+                 VALUES ($1, $1, $2) RETURNING id",
+                name,
+                artist
+            )
+            .fetch_one(&self.db)
+            .await
+            .unwrap();
+            let mut tracks = Vec::new();
+            for number in (1..=count).rev() {
+                let track = self.track().await;
+                sqlx::query!(
+                    "UPDATE tracks SET album_id = $2, track_number = $3 WHERE id = $1",
+                    track,
+                    album,
+                    number
+                )
+                .execute(&self.db)
+                .await
+                .unwrap();
+                sqlx::query!(
+                    "INSERT INTO track_artists (track_id, artist_id, role, position)
+                     VALUES ($1, $2, 'main', 0)",
+                    track,
+                    artist
+                )
+                .execute(&self.db)
+                .await
+                .unwrap();
+                tracks.insert(0, track);
+            }
+            (album, artist, tracks)
+        }
+
+        async fn request(
+            &self,
+            method: Method,
+            uri: &str,
+            token: Option<&str>,
+            body: Option<serde_json::Value>,
+        ) -> (StatusCode, serde_json::Value) {
+            let mut request = Request::builder().method(method).uri(uri);
+            if let Some(token) = token {
+                request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+            }
+            let request = match body {
+                Some(body) => request
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(body.to_string())),
+                None => request.body(Body::empty()),
+            }
+            .unwrap();
+
+            let response = self.router.clone().oneshot(request).await.unwrap();
+            let status = response.status();
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let json = serde_json::from_slice(&bytes).unwrap_or(serde_json::Value::Null);
+            (status, json)
+        }
+
+        /// The playlist's entries, in order, as (entry id, track id).
+        async fn entries(&self, token: &str, id: Uuid) -> Vec<(Uuid, Uuid)> {
+            let uri = format!("/api/playlists/{}", id);
+            let (_, playlist) = self.request(Method::GET, &uri, Some(token), None).await;
+            let id = |value: &serde_json::Value| value.as_str().unwrap().parse().unwrap();
+            playlist["tracks"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|t| (id(&t["entry_id"]), id(&t["id"])))
+                .collect()
+        }
+
+        async fn entry_ids(&self, token: &str, id: Uuid) -> Vec<Uuid> {
+            let entries = self.entries(token, id).await;
+            entries.into_iter().map(|(entry_id, _)| entry_id).collect()
+        }
+
+        /// Adds the track to the playlist, returning the new entry's id.
+        async fn add(&self, token: &str, id: Uuid, track: Uuid) -> Uuid {
+            let (status, body) = self
+                .request(
+                    Method::POST,
+                    &format!("/api/playlists/{}/tracks", id),
+                    Some(token),
+                    Some(serde_json::json!({ "track_id": track })),
+                )
+                .await;
+            assert_eq!(status, StatusCode::OK);
+            body["entry_id"].as_str().unwrap().parse().unwrap()
+        }
+
+        async fn create(&self, token: &str, name: &str) -> Uuid {
+            let (status, body) = self
+                .request(
+                    Method::POST,
+                    "/api/playlists",
+                    Some(token),
+                    Some(serde_json::json!({ "name": name })),
+                )
+                .await;
+            assert_eq!(status, StatusCode::OK);
+            body["id"].as_str().unwrap().parse().unwrap()
+        }
+    }
+
+    impl Drop for TestApp {
+        fn drop(&mut self) {
+            std::fs::remove_dir_all(&self.scratch).ok();
+        }
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "needs Postgres at DATABASE_URL"]
+    async fn requests_without_a_token_are_unauthorized(db: PgPool) {
+        let app = TestApp::new(db);
+        let id = Uuid::new_v4();
+        let requests = [
+            (Method::GET, "/api/playlists".to_string()),
+            (Method::POST, "/api/playlists".to_string()),
+            (Method::GET, format!("/api/playlists/{}", id)),
+            (Method::DELETE, format!("/api/playlists/{}", id)),
+            (Method::POST, format!("/api/playlists/{}/tracks", id)),
+            (
+                Method::DELETE,
+                format!("/api/playlists/{}/tracks/{}", id, id),
+            ),
+            (
+                Method::DELETE,
+                format!("/api/playlists/{}/entries/{}", id, id),
+            ),
+        ];
+        for (method, uri) in requests {
+            let (status, _) = app.request(method.clone(), &uri, None, None).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{} {}", method, uri);
+        }
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "needs Postgres at DATABASE_URL"]
+    async fn playlists_are_owned_and_listed_by_their_creator(db: PgPool) {
+        let app = TestApp::new(db);
+        let (alice_id, alice) = app.user().await;
+        let (_, bob) = app.user().await;
+        let id = app.create(&alice, "Alice's").await;
+
+        let (status, page) = app
+            .request(Method::GET, "/api/playlists", Some(&alice), None)
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(page["total"], 1);
+        assert_eq!(page["items"][0]["id"], id.to_string());
+        assert_eq!(page["items"][0]["user_id"], alice_id.to_string());
+
+        let (status, page) = app
+            .request(Method::GET, "/api/playlists", Some(&bob), None)
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(page["total"], 0);
+        assert_eq!(page["items"], serde_json::json!([]));
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "needs Postgres at DATABASE_URL"]
+    async fn other_users_playlists_are_forbidden(db: PgPool) {
+        let app = TestApp::new(db);
+        let (_, alice) = app.user().await;
+        let (_, bob) = app.user().await;
+        let id = app.create(&alice, "Alice's").await;
+        let track = app.track().await;
+        let entry = app.add(&alice, id, track).await;
+
+        let requests = [
+            (Method::GET, format!("/api/playlists/{}", id), None),
+            (
+                Method::PATCH,
+                format!("/api/playlists/{}", id),
+                Some(serde_json::json!({ "name": "Bob's now" })),
+            ),
+            (
+                Method::POST,
+                format!("/api/playlists/{}/tracks", id),
+                Some(serde_json::json!({ "track_id": track })),
+            ),
+            (
+                Method::PATCH,
+                format!("/api/playlists/{}/tracks", id),
+                Some(serde_json::json!({ "entry_ids": [entry] })),
+            ),
+            (
+                Method::POST,
+                format!("/api/playlists/{}/tracks/batch", id),
+                Some(serde_json::json!({ "track_ids": [track] })),
+            ),
+            (
+                Method::DELETE,
+                format!("/api/playlists/{}/tracks/batch", id),
+                Some(serde_json::json!({ "entry_ids": [entry] })),
+            ),
+            (
+                Method::DELETE,
+                format!("/api/playlists/{}/tracks/{}", id, track),
+                None,
+            ),
+            (
+                Method::DELETE,
+                format!("/api/playlists/{}/entries/{}", id, entry),
+                None,
+            ),
+            (Method::DELETE, format!("/api/playlists/{}", id), None),
+        ];
+        for (method, uri, body) in requests {
+            let (status, _) = app.request(method.clone(), &uri, Some(&bob), body).await;
+            assert_eq!(status, StatusCode::FORBIDDEN, "{} {}", method, uri);
+        }
+
+        // Nothing bob tried went through
+        let (status, playlist) = app
+            .request(
+                Method::GET,
+                &format!("/api/playlists/{}", id),
+                Some(&alice),
+                None,
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(playlist["name"], "Alice's");
+        assert_eq!(playlist["tracks"][0]["entry_id"], entry.to_string());
+        assert_eq!(playlist["tracks"][0]["id"], track.to_string());
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "needs Postgres at DATABASE_URL"]
+    async fn missing_playlists_are_not_found(db: PgPool) {
+        let app = TestApp::new(db);
+        let (_, alice) = app.user().await;
+        let id = Uuid::new_v4();
+        let requests = [
+            (Method::GET, format!("/api/playlists/{}", id), None),
+            (
+                Method::PATCH,
+                format!("/api/playlists/{}", id),
+                Some(serde_json::json!({ "name": "Renamed" })),
+            ),
+            (
+                Method::POST,
+                format!("/api/playlists/{}/tracks", id),
+                Some(serde_json::json!({ "track_id": id })),
+            ),
+            (
+                Method::DELETE,
+                format!("/api/playlists/{}/tracks/{}", id, id),
+                None,
+            ),
+            (
+                Method::DELETE,
+                format!("/api/playlists/{}/entries/{}", id, id),
+                None,
+            ),
+            (Method::DELETE, format!("/api/playlists/{}", id), None),
+        ];
+        for (method, uri, body) in requests {
+            let (status, _) = app.request(method.clone(), &uri, Some(&alice), body).await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{} {}", method, uri);
+        }
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "needs Postgres at DATABASE_URL"]
+    async fn owners_can_edit_and_delete_their_playlists(db: PgPool) {
+        let app = TestApp::new(db);
+        let (_, alice) = app.user().await;
+        let id = app.create(&alice, "Alice's").await;
+        let track = app.track().await;
+
+        let uri = format!("/api/playlists/{}", id);
+        let tracks_uri = format!("{}/tracks", uri);
+        let body = serde_json::json!({ "track_id": track });
+        let (status, _) = app
+            .request(Method::POST, &tracks_uri, Some(&alice), Some(body))
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = app
+            .request(
+                Method::DELETE,
+                &format!("{}/{}", tracks_uri, track),
+                Some(&alice),
+                None,
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, playlist) = app.request(Method::GET, &uri, Some(&alice), None).await;
+        assert_eq!(playlist["tracks"], serde_json::json!([]));
+
+        let (status, _) = app.request(Method::DELETE, &uri, Some(&alice), None).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = app.request(Method::GET, &uri, Some(&alice), None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn order_between_neighbours() {
+        assert_eq!(order_between(None, None), Some(0));
+        assert_eq!(order_between(Some(0), None), Some(ORDER_GAP));
+        assert_eq!(order_between(None, Some(0)), Some(-ORDER_GAP));
+        assert_eq!(order_between(Some(0), Some(ORDER_GAP)), Some(ORDER_GAP / 2));
+        assert_eq!(order_between(Some(4), Some(6)), Some(5));
+        assert_eq!(order_between(Some(4), Some(5)), None);
+        assert_eq!(order_between(Some(4), Some(4)), None);
+    }
+
+    #[test]
+    fn orders_between_neighbours() {
+        assert_eq!(orders_between(None, None, 3), Some(vec![0, 1024, 2048]));
+        assert_eq!(orders_between(Some(10), None, 2), Some(vec![1034, 2058]));
+        assert_eq!(orders_between(None, Some(0), 2), Some(vec![-2048, -1024]));
+        assert_eq!(orders_between(Some(0), Some(8), 3), Some(vec![2, 4, 6]));
+        assert_eq!(orders_between(Some(0), Some(3), 3), None);
+        assert_eq!(orders_between(Some(0), Some(1), 0), Some(vec![]));
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "needs Postgres at DATABASE_URL"]
+    async fn entries_can_be_moved_and_reordered(db: PgPool) {
+        let app = TestApp::new(db);
+        let (_, alice) = app.user().await;
+        let id = app.create(&alice, "Alice's").await;
+        let tracks_uri = format!("/api/playlists/{}/tracks", id);
+        let mut expected = Vec::new();
+        for _ in 0..4 {
+            let track = app.track().await;
+            expected.push(app.add(&alice, id, track).await);
+        }
+        assert_eq!(app.entry_ids(&alice, id).await, expected);
+
+        // Enough moves into the same spot to use up the gap and force a renumbering
+        for (from, to) in [(0, 3), (3, 0), (1, 2)]
+            .into_iter()
+            .chain(std::iter::repeat_n((3, 1), 12))
+        {
+            let body = serde_json::json!({ "from": from, "to": to });
+            let (status, _) = app
+                .request(Method::PATCH, &tracks_uri, Some(&alice), Some(body))
+                .await;
+            assert_eq!(status, StatusCode::OK);
+            let moved = expected.remove(from);
+            expected.insert(to, moved);
+            assert_eq!(app.entry_ids(&alice, id).await, expected);
+        }
+
+        expected.reverse();
+        let body = serde_json::json!({ "entry_ids": expected });
+        let (status, _) = app
+            .request(Method::PATCH, &tracks_uri, Some(&alice), Some(body))
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(app.entry_ids(&alice, id).await, expected);
+
+        // Orders from a client that hasn't seen the latest change
+        let stale = [
+            serde_json::json!({ "from": 0, "to": 1, "entry_id": expected[1] }),
+            serde_json::json!({ "entry_ids": &expected[1..] }),
+            serde_json::json!({ "entry_ids": [expected[0], expected[0], expected[1], expected[2]] }),
+        ];
+        for body in stale {
+            let (status, _) = app
+                .request(Method::PATCH, &tracks_uri, Some(&alice), Some(body))
+                .await;
+            assert_eq!(status, StatusCode::CONFLICT);
+        }
+        let body = serde_json::json!({ "from": 0, "to": 4 });
+        let (status, _) = app
+            .request(Method::PATCH, &tracks_uri, Some(&alice), Some(body))
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(app.entry_ids(&alice, id).await, expected);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "needs Postgres at DATABASE_URL"]
+    async fn tracks_can_repeat_and_be_removed_one_entry_at_a_time(db: PgPool) {
+        let app = TestApp::new(db);
+        let (_, alice) = app.user().await;
+        let id = app.create(&alice, "Alice's").await;
+        let (track, other) = (app.track().await, app.track().await);
+        let first = app.add(&alice, id, track).await;
+        let middle = app.add(&alice, id, other).await;
+        let last = app.add(&alice, id, track).await;
+        assert_eq!(
+            app.entries(&alice, id).await,
+            [(first, track), (middle, other), (last, track)]
+        );
+
+        let uri = format!("/api/playlists/{}/entries/{}", id, first);
+        let (status, _) = app.request(Method::DELETE, &uri, Some(&alice), None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            app.entries(&alice, id).await,
+            [(middle, other), (last, track)]
+        );
+        let (status, _) = app.request(Method::DELETE, &uri, Some(&alice), None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // Removing by track takes every entry of it
+        app.add(&alice, id, track).await;
+        let uri = format!("/api/playlists/{}/tracks/{}", id, track);
+        let (status, _) = app.request(Method::DELETE, &uri, Some(&alice), None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(app.entries(&alice, id).await, [(middle, other)]);
+
+        // Tracks that don't exist can't be added
+        let uri = format!("/api/playlists/{}/tracks", id);
+        let body = serde_json::json!({ "track_id": Uuid::new_v4() });
+        let (status, _) = app
+            .request(Method::POST, &uri, Some(&alice), Some(body))
+            .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(app.entries(&alice, id).await, [(middle, other)]);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "needs Postgres at DATABASE_URL"]
+    async fn playlists_can_be_renamed_and_described(db: PgPool) {
+        let app = TestApp::new(db);
+        let (_, alice) = app.user().await;
+        let id = app.create(&alice, "  Alice's  ").await;
+        let uri = format!("/api/playlists/{}", id);
+        let (_, created) = app.request(Method::GET, &uri, Some(&alice), None).await;
+        assert_eq!(created["name"], "Alice's");
+
+        let body = serde_json::json!({ "name": " Running ", "description": " Fast ones " });
+        let (status, updated) = app
+            .request(Method::PATCH, &uri, Some(&alice), Some(body))
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(updated["name"], "Running");
+        assert_eq!(updated["description"], "Fast ones");
+        assert_ne!(updated["updated_at"], created["updated_at"]);
+
+        // Only what's given changes, and an empty description removes it
+        let body = serde_json::json!({ "description": "" });
+        let (_, cleared) = app
+            .request(Method::PATCH, &uri, Some(&alice), Some(body))
+            .await;
+        assert_eq!(cleared["name"], "Running");
+        assert_eq!(cleared["description"], serde_json::Value::Null);
+
+        let invalid = [
+            serde_json::json!({ "name": "   " }),
+            serde_json::json!({ "name": "x".repeat(101) }),
+            serde_json::json!({ "description": "x".repeat(1001) }),
+        ];
+        for body in invalid {
+            let (status, _) = app
+                .request(Method::PATCH, &uri, Some(&alice), Some(body))
+                .await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+        }
+        let body = serde_json::json!({ "name": " " });
+        let (status, _) = app
+            .request(Method::POST, "/api/playlists", Some(&alice), Some(body))
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        // Changing the entries counts as a change too
+        let track = app.track().await;
+        app.add(&alice, id, track).await;
+        let (_, playlist) = app.request(Method::GET, &uri, Some(&alice), None).await;
+        assert_eq!(playlist["name"], "Running");
+        assert_ne!(playlist["updated_at"], cleared["updated_at"]);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "needs Postgres at DATABASE_URL"]
+    async fn tracks_can_be_added_and_removed_in_batches(db: PgPool) {
+        let app = TestApp::new(db);
+        let (_, alice) = app.user().await;
+        let id = app.create(&alice, "Alice's").await;
+        let batch_uri = format!("/api/playlists/{}/tracks/batch", id);
+        let (album, artist, album_tracks) = app.album(3).await;
+        let (first, missing) = (app.track().await, Uuid::new_v4());
+
+        let body = serde_json::json!({ "track_ids": [first, missing], "album_id": album });
+        let (status, result) = app
+            .request(Method::POST, &batch_uri, Some(&alice), Some(body))
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(result["succeeded"], 4);
+        assert_eq!(result["failed"], 1);
+        let results = result["results"].as_array().unwrap();
+        let statuses: Vec<_> = results.iter().map(|r| r["status"].clone()).collect();
+        assert_eq!(
+            statuses,
+            ["added", "not_found", "added", "added", "added"].map(serde_json::Value::from)
+        );
+        assert_eq!(results[1]["id"], missing.to_string());
+        assert_eq!(results[1].get("entry_id"), None);
+        let mut expected: Vec<(Uuid, Uuid)> = results
+            .iter()
+            .filter(|r| r["status"] == "added")
+            .map(|r| {
+                let id = |v: &serde_json::Value| v.as_str().unwrap().parse().unwrap();
+                (id(&r["entry_id"]), id(&r["id"]))
+            })
+            .collect();
+        let tracks: Vec<Uuid> = expected.iter().map(|(_, track)| *track).collect();
+        assert_eq!(tracks[0], first);
+        assert_eq!(tracks[1..], album_tracks);
+        assert_eq!(app.entries(&alice, id).await, expected);
+
+        // Removed tracks are as good as missing
+        let removed = app.track().await;
+        sqlx::query!(
+            "UPDATE tracks SET removed_at = now() WHERE id = $1",
+            removed
+        )
+        .execute(&app.db)
+        .await
+        .unwrap();
+        let body = serde_json::json!({ "track_ids": [removed] });
+        let (status, result) = app
+            .request(Method::POST, &batch_uri, Some(&alice), Some(body))
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(result["results"][0]["status"], "not_found");
+        assert_eq!(app.entries(&alice, id).await, expected);
+
+        // Into the same spot again and again, until it has to be renumbered
+        for _ in 0..6 {
+            let body = serde_json::json!({ "artist_id": artist, "position": 1 });
+            let (status, result) = app
+                .request(Method::POST, &batch_uri, Some(&alice), Some(body))
+                .await;
+            assert_eq!(status, StatusCode::OK);
+            let added = result["results"].as_array().unwrap().iter().map(|r| {
+                let id = |v: &serde_json::Value| v.as_str().unwrap().parse().unwrap();
+                (id(&r["entry_id"]), id(&r["id"]))
+            });
+            expected.splice(1..1, added);
+            assert_eq!(app.entries(&alice, id).await, expected);
+        }
+
+        let body = serde_json::json!({ "track_ids": [first], "position": expected.len() + 1 });
+        let (status, _) = app
+            .request(Method::POST, &batch_uri, Some(&alice), Some(body))
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let body = serde_json::json!({ "album_id": missing });
+        let (status, _) = app
+            .request(Method::POST, &batch_uri, Some(&alice), Some(body))
+            .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (first_entry, _) = expected[0];
+        let body = serde_json::json!({
+            "track_ids": [album_tracks[0], missing],
+            "entry_ids": [first_entry, missing],
+        });
+        let (status, result) = app
+            .request(Method::DELETE, &batch_uri, Some(&alice), Some(body))
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(result["succeeded"], 2);
+        assert_eq!(result["failed"], 2);
+        let statuses: Vec<_> = result["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["status"].clone())
+            .collect();
+        assert_eq!(
+            statuses,
+            ["removed", "not_found", "removed", "not_found"].map(serde_json::Value::from)
+        );
+        expected.retain(|(entry, track)| *entry != first_entry && *track != album_tracks[0]);
+        assert_eq!(app.entries(&alice, id).await, expected);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    #[ignore = "needs Postgres at DATABASE_URL"]
+    async fn search_only_finds_the_callers_playlists(db: PgPool) {
+        let app = TestApp::new(db);
+        let (_, alice) = app.user().await;
+        let (_, bob) = app.user().await;
+        let id = app.create(&alice, "Midnight Drive").await;
+        let uri = "/api/search?q=midnight";
+
+        let (status, results) = app.request(Method::GET, uri, Some(&alice), None).await;
+        assert_eq!(status, StatusCode::OK);
+        let found: Vec<_> = results["playlists"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|p| p["id"].clone())
+            .collect();
+        assert_eq!(found, [serde_json::Value::from(id.to_string())]);
+
+        let (status, results) = app.request(Method::GET, uri, Some(&bob), None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(results["playlists"], serde_json::json!([]));
+
+        let (status, _) = app.request(Method::GET, uri, None, None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+}
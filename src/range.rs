@@ -0,0 +1,313 @@
+//! `Range` / `If-Range` handling for byte ranges (RFC 7233).
+
+use std::time::SystemTime;
+
+/// More ranges than this and the header is ignored, serving the full body instead.
+const MAX_RANGES: usize = 16;
+
+/// An inclusive byte range, already clamped to the representation length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    /// Value for the `Content-Range` header.
+    pub fn content_range(&self, total: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, total)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RangeRequest {
+    /// No usable `Range` header: send the whole representation with 200.
+    Full,
+    /// 206 with one range, or `multipart/byteranges` for several.
+    Partial(Vec<ByteRange>),
+    /// 416 with `Content-Range: bytes */len`.
+    Unsatisfiable,
+}
+
+/// Interprets a `Range` header against a representation of `len` bytes.
+///
+/// Syntactically invalid headers and unknown units are ignored, as the RFC requires.
+/// Overlapping or adjacent ranges are coalesced.
+pub fn parse(header: Option<&str>, len: u64) -> RangeRequest {
+    let Some(header) = header else {
+        return RangeRequest::Full;
+    };
+    let Some((unit, specs)) = header.split_once('=') else {
+        return RangeRequest::Full;
+    };
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return RangeRequest::Full;
+    }
+
+    let mut ranges = Vec::new();
+    let mut any_spec = false;
+    for spec in specs.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        any_spec = true;
+        match parse_spec(spec, len) {
+            Spec::Invalid => return RangeRequest::Full,
+            Spec::Unsatisfiable => {}
+            Spec::Range(range) => ranges.push(range),
+        }
+    }
+
+    if !any_spec {
+        return RangeRequest::Full;
+    }
+    if ranges.is_empty() {
+        return RangeRequest::Unsatisfiable;
+    }
+
+    let ranges = coalesce(ranges);
+    if ranges.len() > MAX_RANGES {
+        return RangeRequest::Full;
+    }
+    RangeRequest::Partial(ranges)
+}
+
+enum Spec {
+    Range(ByteRange),
+    Unsatisfiable,
+    Invalid,
+}
+
+fn parse_spec(spec: &str, len: u64) -> Spec {
+    let Some((first, last)) = spec.split_once('-') else {
+        return Spec::Invalid;
+    };
+    let (first, last) = (first.trim(), last.trim());
+
+    // `-500`: the final 500 bytes
+    if first.is_empty() {
+        let Some(suffix) = parse_number(last) else {
+            return Spec::Invalid;
+        };
+        if suffix == 0 || len == 0 {
+            return Spec::Unsatisfiable;
+        }
+        return Spec::Range(ByteRange {
+            start: len.saturating_sub(suffix),
+            end: len - 1,
+        });
+    }
+
+    let Some(start) = parse_number(first) else {
+        return Spec::Invalid;
+    };
+    let end = if last.is_empty() {
+        None
+    } else {
+        match parse_number(last) {
+            Some(end) if end >= start => Some(end),
+            _ => return Spec::Invalid,
+        }
+    };
+
+    if start >= len {
+        return Spec::Unsatisfiable;
+    }
+    Spec::Range(ByteRange {
+        start,
+        end: end.map_or(len - 1, |end| end.min(len - 1)),
+    })
+}
+
+fn parse_number(value: &str) -> Option<u64> {
+    if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    value.parse().ok()
+}
+
+fn coalesce(mut ranges: Vec<ByteRange>) -> Vec<ByteRange> {
+    ranges.sort_by_key(|r| r.start);
+    let mut merged: Vec<ByteRange> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end.saturating_add(1) => {
+                last.end = last.end.max(range.end);
+            }
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+/// Whether an `If-Range` precondition holds, i.e. the client's cached copy is current
+/// and the `Range` header may be honoured.
+///
+/// Only strong validators count: a weak ETag never matches, and a date must equal
+/// `Last-Modified` exactly.
+pub fn if_range_matches(if_range: &str, etag: &str, last_modified: SystemTime) -> bool {
+    let if_range = if_range.trim();
+    if if_range.starts_with('"') {
+        return if_range == etag;
+    }
+    if if_range.starts_with("W/") {
+        return false;
+    }
+    match httpdate::parse_http_date(if_range) {
+        Ok(date) => httpdate::fmt_http_date(date) == httpdate::fmt_http_date(last_modified),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn r(start: u64, end: u64) -> ByteRange {
+        ByteRange { start, end }
+    }
+
+    #[test]
+    fn missing_header_is_full() {
+        assert_eq!(parse(None, 100), RangeRequest::Full);
+    }
+
+    #[test]
+    fn closed_range() {
+        assert_eq!(
+            parse(Some("bytes=0-9"), 100),
+            RangeRequest::Partial(vec![r(0, 9)])
+        );
+    }
+
+    #[test]
+    fn open_ended_range() {
+        assert_eq!(
+            parse(Some("bytes=90-"), 100),
+            RangeRequest::Partial(vec![r(90, 99)])
+        );
+    }
+
+    #[test]
+    fn end_past_length_is_clamped() {
+        assert_eq!(
+            parse(Some("bytes=50-1000"), 100),
+            RangeRequest::Partial(vec![r(50, 99)])
+        );
+    }
+
+    #[test]
+    fn suffix_range() {
+        assert_eq!(
+            parse(Some("bytes=-10"), 100),
+            RangeRequest::Partial(vec![r(90, 99)])
+        );
+    }
+
+    #[test]
+    fn suffix_longer_than_file_is_whole_file() {
+        assert_eq!(
+            parse(Some("bytes=-500"), 100),
+            RangeRequest::Partial(vec![r(0, 99)])
+        );
+    }
+
+    #[test]
+    fn zero_suffix_is_unsatisfiable() {
+        assert_eq!(parse(Some("bytes=-0"), 100), RangeRequest::Unsatisfiable);
+    }
+
+    #[test]
+    fn multiple_ranges() {
+        assert_eq!(
+            parse(Some("bytes=0-9, 20-29,-5"), 100),
+            RangeRequest::Partial(vec![r(0, 9), r(20, 29), r(95, 99)])
+        );
+    }
+
+    #[test]
+    fn overlapping_ranges_are_coalesced() {
+        assert_eq!(
+            parse(Some("bytes=10-20,0-12,21-25"), 100),
+            RangeRequest::Partial(vec![r(0, 25)])
+        );
+    }
+
+    #[test]
+    fn start_past_length_is_unsatisfiable() {
+        assert_eq!(parse(Some("bytes=100-"), 100), RangeRequest::Unsatisfiable);
+    }
+
+    #[test]
+    fn unsatisfiable_specs_are_dropped_when_others_match() {
+        assert_eq!(
+            parse(Some("bytes=200-300,0-0"), 100),
+            RangeRequest::Partial(vec![r(0, 0)])
+        );
+    }
+
+    #[test]
+    fn empty_file_is_unsatisfiable() {
+        assert_eq!(parse(Some("bytes=0-"), 0), RangeRequest::Unsatisfiable);
+        assert_eq!(parse(Some("bytes=-1"), 0), RangeRequest::Unsatisfiable);
+    }
+
+    #[test]
+    fn invalid_syntax_is_ignored() {
+        assert_eq!(parse(Some("bytes=9-0"), 100), RangeRequest::Full);
+        assert_eq!(parse(Some("bytes=a-b"), 100), RangeRequest::Full);
+        assert_eq!(parse(Some("bytes=5"), 100), RangeRequest::Full);
+        assert_eq!(parse(Some("bytes=+1-2"), 100), RangeRequest::Full);
+        assert_eq!(parse(Some("bytes="), 100), RangeRequest::Full);
+        assert_eq!(parse(Some("0-10"), 100), RangeRequest::Full);
+    }
+
+    #[test]
+    fn other_units_are_ignored() {
+        assert_eq!(parse(Some("items=0-5"), 100), RangeRequest::Full);
+    }
+
+    #[test]
+    fn too_many_ranges_are_ignored() {
+        let header = format!(
+            "bytes={}",
+            (0..20)
+                .map(|i| format!("{}-{}", i * 10, i * 10 + 1))
+                .collect::<Vec<_>>()
+                .join(",")
+        );
+        assert_eq!(parse(Some(&header), 1000), RangeRequest::Full);
+    }
+
+    #[test]
+    fn content_range_header() {
+        assert_eq!(r(0, 9).content_range(100), "bytes 0-9/100");
+        assert_eq!(r(0, 9).len(), 10);
+    }
+
+    #[test]
+    fn if_range_etag() {
+        let modified = SystemTime::UNIX_EPOCH;
+        assert!(if_range_matches("\"abc\"", "\"abc\"", modified));
+        assert!(!if_range_matches("\"abd\"", "\"abc\"", modified));
+        assert!(!if_range_matches("W/\"abc\"", "\"abc\"", modified));
+    }
+
+    #[test]
+    fn if_range_date() {
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(784_111_777);
+        assert!(if_range_matches(
+            "Sun, 06 Nov 1994 08:49:37 GMT",
+            "\"abc\"",
+            modified
+        ));
+        assert!(!if_range_matches(
+            "Sun, 06 Nov 1994 08:49:38 GMT",
+            "\"abc\"",
+            modified
+        ));
+        assert!(!if_range_matches("yesterday", "\"abc\"", modified));
+    }
+}
@@ -0,0 +1,244 @@
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use walkdir::{DirEntry, WalkDir};
+
+pub const AUDIO_EXTENSIONS: &[&str] = &["mp3", "wav", "ogg", "flac", "m4a"];
+
+/// How a library directory is walked when looking for audio files.
+#[derive(Debug, Clone)]
+pub struct ScanOptions {
+    /// Maximum directory depth below the root, `None` for unlimited.
+    pub max_depth: Option<usize>,
+    pub follow_symlinks: bool,
+    pub include_hidden: bool,
+    /// Globs matched against the path relative to the root. Empty means everything.
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            max_depth: None,
+            follow_symlinks: true,
+            include_hidden: false,
+            include: Vec::new(),
+            exclude: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ScanFailure {
+    pub path: PathBuf,
+    pub error: String,
+}
+
+impl ScanOptions {
+    /// Reads `SCAN_MAX_DEPTH`, `SCAN_FOLLOW_SYMLINKS`, `SCAN_INCLUDE_HIDDEN` and the
+    /// comma separated glob lists `SCAN_INCLUDE` / `SCAN_EXCLUDE`.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut options = Self::default();
+        if let Ok(depth) = std::env::var("SCAN_MAX_DEPTH") {
+            options.max_depth = Some(depth.parse()?);
+        }
+        if let Ok(follow) = std::env::var("SCAN_FOLLOW_SYMLINKS") {
+            options.follow_symlinks = follow.parse()?;
+        }
+        if let Ok(hidden) = std::env::var("SCAN_INCLUDE_HIDDEN") {
+            options.include_hidden = hidden.parse()?;
+        }
+        options.include = env_list("SCAN_INCLUDE");
+        options.exclude = env_list("SCAN_EXCLUDE");
+        Ok(options)
+    }
+
+    /// Walks `dir`, which is `root` or a directory inside it, and returns every audio
+    /// file that passes the filters, along with entries that could not be read
+    /// (permission errors, symlink loops, ...). Depth and globs are measured from
+    /// `root`, so a subdirectory gives the part of a full scan that falls under it.
+    pub fn collect_within(
+        &self,
+        root: &Path,
+        dir: &Path,
+    ) -> anyhow::Result<(Vec<PathBuf>, Vec<ScanFailure>)> {
+        let include = build_globset(&self.include)?;
+        let exclude = build_globset(&self.exclude)?;
+
+        let below = dir
+            .strip_prefix(root)
+            .map_err(|_| anyhow::anyhow!("{:?} is not inside {:?}", dir, root))?;
+        let hidden = below
+            .components()
+            .any(|c| c.as_os_str().to_string_lossy().starts_with('.'));
+        if hidden && !self.include_hidden {
+            return Ok((Vec::new(), Vec::new()));
+        }
+
+        let mut walker = WalkDir::new(dir).follow_links(self.follow_symlinks);
+        if let Some(depth) = self.max_depth {
+            // depth 0 is the root itself, files directly inside it are at depth 1
+            let Some(depth) = (depth + 1).checked_sub(below.components().count()) else {
+                return Ok((Vec::new(), Vec::new()));
+            };
+            walker = walker.max_depth(depth);
+        }
+
+        let mut files = Vec::new();
+        let mut failures = Vec::new();
+
+        let entries = walker
+            .into_iter()
+            .filter_entry(|e| e.depth() == 0 || self.include_hidden || !is_hidden(e));
+
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    // walkdir reports symlink loops here instead of recursing forever
+                    failures.push(ScanFailure {
+                        path: e.path().map(Path::to_path_buf).unwrap_or_else(|| root.into()),
+                        error: e.to_string(),
+                    });
+                    continue;
+                }
+            };
+
+            if !entry.file_type().is_file() {
+                continue;
+            }
+
+            let relative = entry.path().strip_prefix(root).unwrap_or(entry.path());
+            if self.accepts(relative, &include, &exclude) {
+                files.push(entry.into_path());
+            }
+        }
+
+        Ok((files, failures))
+    }
+
+    /// Whether a single file below `root` would have been picked up by [`Self::collect_within`].
+    pub fn matches(&self, root: &Path, path: &Path) -> anyhow::Result<bool> {
+        let Ok(relative) = path.strip_prefix(root) else {
+            return Ok(false);
+        };
+
+        if let Some(depth) = self.max_depth {
+            if relative.components().count() > depth + 1 {
+                return Ok(false);
+            }
+        }
+        let hidden = relative
+            .components()
+            .any(|c| c.as_os_str().to_string_lossy().starts_with('.'));
+        if hidden && !self.include_hidden {
+            return Ok(false);
+        }
+
+        let include = build_globset(&self.include)?;
+        let exclude = build_globset(&self.exclude)?;
+        Ok(self.accepts(relative, &include, &exclude))
+    }
+
+    fn accepts(&self, relative: &Path, include: &GlobSet, exclude: &GlobSet) -> bool {
+        is_audio(relative)
+            && (self.include.is_empty() || include.is_match(relative))
+            && !exclude.is_match(relative)
+    }
+}
+
+pub fn is_audio(path: &Path) -> bool {
+    path.extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .is_some_and(|ext| AUDIO_EXTENSIONS.contains(&ext.as_str()))
+}
+
+/// Detects the audio MIME type from the leading bytes, ignoring the file extension.
+pub fn sniff_audio(buf: &[u8]) -> Option<&'static str> {
+    infer::get(buf)
+        .filter(|kind| kind.matcher_type() == infer::MatcherType::Audio)
+        .map(|kind| match kind.mime_type() {
+            // infer reports legacy names; store the registered ones browsers expect
+            "audio/x-flac" => "audio/flac",
+            "audio/m4a" => "audio/mp4",
+            mime => mime,
+        })
+}
+
+fn is_hidden(entry: &DirEntry) -> bool {
+    entry.file_name().to_string_lossy().starts_with('.')
+}
+
+fn build_globset(patterns: &[String]) -> anyhow::Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern)?);
+    }
+    Ok(builder.build()?)
+}
+
+fn env_list(name: &str) -> Vec<String> {
+    std::env::var(name)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sniffed_mime_types_are_normalised() {
+        assert_eq!(sniff_audio(b"fLaC\0\0\0\x22"), Some("audio/flac"));
+        assert_eq!(
+            sniff_audio(b"\0\0\0\x20ftypM4A \0\0\0\0"),
+            Some("audio/mp4")
+        );
+        assert_eq!(sniff_audio(b"ID3\x03\0\0\0\0\0\0"), Some("audio/mpeg"));
+        assert_eq!(sniff_audio(b"not audio at all"), None);
+    }
+
+    #[test]
+    fn subdirectories_are_scanned_as_part_of_the_root() {
+        let root = std::env::temp_dir().join(format!("arcsin-scan-{}", uuid::Uuid::new_v4()));
+        for file in [
+            "a.mp3",
+            "live/b.mp3",
+            "live/deep/c.mp3",
+            "live/deep/er/d.mp3",
+        ] {
+            let path = root.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, b"").unwrap();
+        }
+        let options = ScanOptions {
+            max_depth: Some(2),
+            exclude: vec!["live/b.mp3".to_string()],
+            ..Default::default()
+        };
+        let names = |files: Vec<PathBuf>| {
+            let mut names: Vec<_> = files
+                .iter()
+                .map(|f| f.strip_prefix(&root).unwrap().to_path_buf())
+                .collect();
+            names.sort();
+            names
+        };
+
+        let (full, _) = options.collect_within(&root, &root).unwrap();
+        let (within, _) = options.collect_within(&root, &root.join("live")).unwrap();
+        assert_eq!(names(within), [PathBuf::from("live/deep/c.mp3")]);
+        assert_eq!(names(full).len(), 2);
+        let (past_depth, _) = options
+            .collect_within(&root, &root.join("live/deep/er"))
+            .unwrap();
+        assert!(past_depth.is_empty());
+        assert!(options.collect_within(&root.join("live"), &root).is_err());
+        std::fs::remove_dir_all(root).ok();
+    }
+}
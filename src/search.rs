@@ -0,0 +1,350 @@
+//! `/api/search?q=`, across tracks, artists, albums and playlists.
+//!
+//! Every word of the query is matched as a prefix against the `search_vector` columns,
+//! which fold accents, so results show up while the user is still typing. Misspellings
+//! are caught by trigram word similarity on the names, over the same unaccented text.
+//! Playlists are private, so only the searching user's own show up.
+
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    response::Response,
+    Extension,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::auth::{self, Claims};
+use crate::cache;
+use crate::models::TrackRecord;
+use crate::AppState;
+
+const DEFAULT_LIMIT: i64 = 10;
+const MAX_LIMIT: i64 = 50;
+/// Wrap matches in `ts_headline`, later split into fragments. Control characters, so
+/// they can't clash with anything in a title.
+const MATCH_START: char = '\u{1}';
+const MATCH_END: char = '\u{2}';
+
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    pub q: String,
+    /// Results per entity type.
+    pub limit: Option<i64>,
+}
+
+/// A piece of highlighted text; `matched` pieces are what the query hit.
+#[derive(Debug, Serialize)]
+pub struct Fragment {
+    pub text: String,
+    pub matched: bool,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct TrackHit {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub track: TrackRecord,
+    #[serde(skip)]
+    pub headline: String,
+    #[sqlx(skip)]
+    pub highlight: Vec<Fragment>,
+    pub rank: f32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ArtistHit {
+    pub id: Uuid,
+    pub name: String,
+    pub highlight: Vec<Fragment>,
+    pub rank: f32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AlbumHit {
+    pub id: Uuid,
+    pub title: String,
+    pub year: Option<i32>,
+    pub artist_name: Option<String>,
+    pub highlight: Vec<Fragment>,
+    pub rank: f32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlaylistHit {
+    pub id: Uuid,
+    pub name: String,
+    pub description: Option<String>,
+    pub highlight: Vec<Fragment>,
+    pub rank: f32,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct SearchResults {
+    pub tracks: Vec<TrackHit>,
+    pub artists: Vec<ArtistHit>,
+    pub albums: Vec<AlbumHit>,
+    pub playlists: Vec<PlaylistHit>,
+}
+
+/// `ts_headline` output split at the match markers.
+fn fragments(headline: &str) -> Vec<Fragment> {
+    let mut fragments = Vec::new();
+    for (i, part) in headline.split(MATCH_START).enumerate() {
+        // Everything after a start marker is a match up to its end marker
+        let (matched, rest) = match part.split_once(MATCH_END) {
+            Some((matched, rest)) if i > 0 => (matched, rest),
+            _ => ("", part),
+        };
+        for (text, matched) in [(matched, true), (rest, false)] {
+            if !text.is_empty() {
+                fragments.push(Fragment {
+                    text: text.to_string(),
+                    matched,
+                });
+            }
+        }
+    }
+    fragments
+}
+
+/// Each word of `q` as a prefix, all required. Only letters and digits make it in, so
+/// the result is always a valid `tsquery`.
+fn prefix_query(q: &str) -> String {
+    q.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| format!("{}:*", w))
+        .collect::<Vec<_>>()
+        .join(" & ")
+}
+
+fn search_error(e: sqlx::Error) -> StatusCode {
+    eprintln!("Error searching: {}", e);
+    StatusCode::INTERNAL_SERVER_ERROR
+}
+
+/// `GET /api/search?q=&limit=`
+pub async fn search(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<SearchQuery>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+    if limit < 1 {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let limit = limit.min(MAX_LIMIT);
+    let q = query.q.trim();
+    let tsquery = prefix_query(q);
+    if tsquery.is_empty() {
+        return Ok(cache::json(&headers, &SearchResults::default()));
+    }
+    let options = format!(
+        "StartSel={}, StopSel={}, HighlightAll=true",
+        MATCH_START, MATCH_END
+    );
+    let db = &state.app.db;
+    let user_id = auth::user_id(db, &claims).await?;
+
+    let mut tracks = sqlx::query_as::<sqlx::Postgres, TrackHit>(&format!(
+        r#"
+        SELECT t.*, ts_headline('arcsin_search', t.title, to_tsquery('arcsin_search', $1), $4)
+                   AS headline
+        FROM (
+            SELECT {},
+                   (ts_rank(search_vector, to_tsquery('arcsin_search', $1))
+                    + word_similarity(f_unaccent($2), f_unaccent(title))
+                    + word_similarity(f_unaccent($2), f_unaccent(coalesce(artist, ''))) / 2
+                   )::REAL AS rank
+            FROM tracks
+            WHERE removed_at IS NULL
+              AND (search_vector @@ to_tsquery('arcsin_search', $1)
+                   OR f_unaccent($2) <% f_unaccent(title)
+                   OR f_unaccent($2) <% f_unaccent(artist))
+            ORDER BY rank DESC
+            LIMIT $3
+        ) t
+        ORDER BY t.rank DESC
+        "#,
+        TrackRecord::columns("tracks")
+    ))
+    .bind(&tsquery)
+    .bind(q)
+    .bind(limit)
+    .bind(&options)
+    .fetch_all(db)
+    .await
+    .map_err(search_error)?;
+    for hit in &mut tracks {
+        hit.highlight = fragments(&hit.headline);
+    }
+
+    let artists = sqlx::query!(
+        r#"
+        SELECT a.id, a.name, a.rank AS "rank!",
+               ts_headline('arcsin_search', a.name, to_tsquery('arcsin_search', $1), $4)
+                   AS "headline!"
+        FROM (
+            SELECT id, name,
+                   (ts_rank(search_vector, to_tsquery('arcsin_search', $1))
+                    + word_similarity(f_unaccent($2), f_unaccent(name)))::REAL AS rank
+            FROM artists
+            WHERE search_vector @@ to_tsquery('arcsin_search', $1)
+               OR f_unaccent($2) <% f_unaccent(name)
+            ORDER BY rank DESC
+            LIMIT $3
+        ) a
+        ORDER BY a.rank DESC
+        "#,
+        tsquery,
+        q,
+        limit,
+        options
+    )
+    .fetch_all(db)
+    .await
+    .map_err(search_error)?
+    .into_iter()
+    .map(|row| ArtistHit {
+        id: row.id,
+        name: row.name,
+        highlight: fragments(&row.headline),
+        rank: row.rank,
+    })
+    .collect();
+
+    let albums = sqlx::query!(
+        r#"
+        SELECT al.id, al.title, al.year, ar.name AS "artist_name?", al.rank AS "rank!",
+               ts_headline('arcsin_search', al.title, to_tsquery('arcsin_search', $1), $4)
+                   AS "headline!"
+        FROM (
+            SELECT id, title, year, artist_id,
+                   (ts_rank(search_vector, to_tsquery('arcsin_search', $1))
+                    + word_similarity(f_unaccent($2), f_unaccent(title)))::REAL AS rank
+            FROM albums
+            WHERE search_vector @@ to_tsquery('arcsin_search', $1)
+               OR f_unaccent($2) <% f_unaccent(title)
+            ORDER BY rank DESC
+            LIMIT $3
+        ) al
+        LEFT JOIN artists ar ON ar.id = al.artist_id
+        ORDER BY al.rank DESC
+        "#,
+        tsquery,
+        q,
+        limit,
+        options
+    )
+    .fetch_all(db)
+    .await
+    .map_err(search_error)?
+    .into_iter()
+    .map(|row| AlbumHit {
+        id: row.id,
+        title: row.title,
+        year: row.year,
+        artist_name: row.artist_name,
+        highlight: fragments(&row.headline),
+        rank: row.rank,
+    })
+    .collect();
+
+    let playlists = sqlx::query!(
+        r#"
+        SELECT p.id, p.name, p.description, p.rank AS "rank!",
+               ts_headline('arcsin_search', p.name, to_tsquery('arcsin_search', $1), $4)
+                   AS "headline!"
+        FROM (
+            SELECT id, name, description,
+                   (ts_rank(search_vector, to_tsquery('arcsin_search', $1))
+                    + word_similarity(f_unaccent($2), f_unaccent(name)))::REAL AS rank
+            FROM playlists
+            WHERE user_id = $5
+              AND (search_vector @@ to_tsquery('arcsin_search', $1)
+                   OR f_unaccent($2) <% f_unaccent(name))
+            ORDER BY rank DESC
+            LIMIT $3
+        ) p
+        ORDER BY p.rank DESC
+        "#,
+        tsquery,
+        q,
+        limit,
+        options,
+        user_id
+    )
+    .fetch_all(db)
+    .await
+    .map_err(search_error)?
+    .into_iter()
+    .map(|row| PlaylistHit {
+        id: row.id,
+        name: row.name,
+        description: row.description,
+        highlight: fragments(&row.headline),
+        rank: row.rank,
+    })
+    .collect();
+
+    Ok(cache::json(
+        &headers,
+        &SearchResults {
+            tracks,
+            artists,
+            albums,
+            playlists,
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parts(headline: &str) -> Vec<(String, bool)> {
+        fragments(headline)
+            .into_iter()
+            .map(|f| (f.text, f.matched))
+            .collect()
+    }
+
+    #[test]
+    fn fragments_split_at_match_markers() {
+        assert_eq!(
+            parts("Hey \u{1}Jude\u{2}, don't"),
+            vec![
+                ("Hey ".to_string(), false),
+                ("Jude".to_string(), true),
+                (", don't".to_string(), false),
+            ]
+        );
+        assert_eq!(
+            parts("\u{1}Let\u{2} \u{1}It\u{2} Be"),
+            vec![
+                ("Let".to_string(), true),
+                (" ".to_string(), false),
+                ("It".to_string(), true),
+                (" Be".to_string(), false),
+            ]
+        );
+        assert_eq!(parts("No match"), vec![("No match".to_string(), false)]);
+        assert!(parts("").is_empty());
+    }
+
+    #[test]
+    fn fragments_keep_stray_end_markers_as_text() {
+        assert_eq!(parts("a\u{2}b"), vec![("a\u{2}b".to_string(), false)]);
+    }
+
+    #[test]
+    fn prefix_query_keeps_only_words() {
+        assert_eq!(prefix_query("hey jude"), "hey:* & jude:*");
+        assert_eq!(prefix_query("  Beyoncé!  "), "Beyoncé:*");
+        assert_eq!(prefix_query("a&b | !c:* (d)"), "a:* & b:* & c:* & d:*");
+        assert_eq!(prefix_query("AC/DC"), "AC:* & DC:*");
+        assert_eq!(prefix_query("'); --"), "");
+    }
+}
@@ -0,0 +1,261 @@
+use async_trait::async_trait;
+use axum::body::Bytes;
+use futures_util::stream::{BoxStream, StreamExt, TryStreamExt};
+use object_store::aws::{AmazonS3, AmazonS3Builder};
+use object_store::path::Path as ObjectPath;
+use object_store::{GetOptions, GetRange, ObjectStore, PutPayload};
+use std::io::SeekFrom;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+/// Chunk size for streamed reads, which is also the most a reader buffers at once.
+pub const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+pub type ByteStream = BoxStream<'static, std::io::Result<Bytes>>;
+
+/// Where track audio lives. Keys are the track's content hash, so storing the same
+/// bytes twice is a no-op and blobs never need renaming.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    async fn put(&self, key: &str, data: Bytes) -> anyhow::Result<()>;
+    /// Streams just the bytes in `range` without loading the whole blob.
+    async fn get_range(&self, key: &str, range: Range<u64>) -> anyhow::Result<ByteStream>;
+    async fn exists(&self, key: &str) -> anyhow::Result<bool>;
+    async fn delete(&self, key: &str) -> anyhow::Result<()>;
+}
+
+/// Reads a whole blob of `len` bytes into memory, for things that can't be streamed.
+pub async fn read_all(storage: &dyn Storage, key: &str, len: u64) -> anyhow::Result<Vec<u8>> {
+    let data = storage
+        .get_range(key, 0..len)
+        .await?
+        .try_fold(
+            Vec::with_capacity(len as usize),
+            |mut data, chunk| async move {
+                data.extend_from_slice(&chunk);
+                Ok(data)
+            },
+        )
+        .await?;
+    Ok(data)
+}
+
+/// Picks the backend from `STORAGE_BACKEND` (`local`, the default, or `s3`).
+pub fn from_env() -> anyhow::Result<Arc<dyn Storage>> {
+    let backend = std::env::var("STORAGE_BACKEND").unwrap_or_else(|_| "local".to_string());
+    match backend.as_str() {
+        "local" => {
+            let root = std::env::var("STORAGE_DIR").unwrap_or_else(|_| "storage".to_string());
+            Ok(Arc::new(LocalStorage::new(root)))
+        }
+        "s3" => Ok(Arc::new(S3Storage::from_env()?)),
+        other => anyhow::bail!("Unknown STORAGE_BACKEND: {}", other),
+    }
+}
+
+/// Content-addressed files on local disk, fanned out as `ab/cd/abcd...`.
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        match (key.get(..2), key.get(2..4)) {
+            (Some(a), Some(b)) => self.root.join(a).join(b).join(key),
+            _ => self.root.join(key),
+        }
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn put(&self, key: &str, data: Bytes) -> anyhow::Result<()> {
+        let path = self.path(key);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        // Write next to the target and rename so readers never see a partial blob
+        let tmp = path.with_extension(format!("tmp-{}", uuid::Uuid::new_v4()));
+        fs::write(&tmp, &data).await?;
+        fs::rename(&tmp, &path).await?;
+        Ok(())
+    }
+
+    async fn get_range(&self, key: &str, range: Range<u64>) -> anyhow::Result<ByteStream> {
+        let mut file = fs::File::open(self.path(key)).await?;
+        file.seek(SeekFrom::Start(range.start)).await?;
+        let reader = file.take(range.end - range.start);
+        Ok(ReaderStream::with_capacity(reader, STREAM_CHUNK_SIZE).boxed())
+    }
+
+    async fn exists(&self, key: &str) -> anyhow::Result<bool> {
+        Ok(fs::try_exists(self.path(key)).await?)
+    }
+
+    async fn delete(&self, key: &str) -> anyhow::Result<()> {
+        match fs::remove_file(self.path(key)).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Any S3-compatible service (AWS, MinIO, ...), configured through the standard
+/// `AWS_*` variables: `AWS_BUCKET`, `AWS_ENDPOINT`, `AWS_ACCESS_KEY_ID`,
+/// `AWS_SECRET_ACCESS_KEY`, `AWS_REGION` and `AWS_ALLOW_HTTP=true` for local MinIO.
+pub struct S3Storage {
+    store: AmazonS3,
+    prefix: String,
+}
+
+impl S3Storage {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let store = AmazonS3Builder::from_env().build()?;
+        let prefix = std::env::var("S3_PREFIX").unwrap_or_else(|_| "tracks".to_string());
+        Ok(Self { store, prefix })
+    }
+
+    fn path(&self, key: &str) -> ObjectPath {
+        ObjectPath::from(format!("{}/{}", self.prefix, key))
+    }
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+    async fn put(&self, key: &str, data: Bytes) -> anyhow::Result<()> {
+        self.store
+            .put(&self.path(key), PutPayload::from_bytes(data))
+            .await?;
+        Ok(())
+    }
+
+    async fn get_range(&self, key: &str, range: Range<u64>) -> anyhow::Result<ByteStream> {
+        let options = GetOptions {
+            range: Some(GetRange::Bounded(range)),
+            ..Default::default()
+        };
+        let result = self.store.get_opts(&self.path(key), options).await?;
+        Ok(result.into_stream().map_err(std::io::Error::other).boxed())
+    }
+
+    async fn exists(&self, key: &str) -> anyhow::Result<bool> {
+        match self.store.head(&self.path(key)).await {
+            Ok(_) => Ok(true),
+            Err(object_store::Error::NotFound { .. }) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> anyhow::Result<()> {
+        match self.store.delete(&self.path(key)).await {
+            Err(object_store::Error::NotFound { .. }) | Ok(()) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch() -> PathBuf {
+        std::env::temp_dir().join(format!("arcsin-storage-{}", uuid::Uuid::new_v4()))
+    }
+
+    async fn read(storage: &dyn Storage, key: &str, range: Range<u64>) -> Vec<u8> {
+        storage
+            .get_range(key, range)
+            .await
+            .unwrap()
+            .try_fold(Vec::new(), |mut data, chunk| async move {
+                data.extend_from_slice(&chunk);
+                Ok(data)
+            })
+            .await
+            .unwrap()
+    }
+
+    /// What every backend has to do, against a key no one else uses.
+    async fn behaves_like_storage(storage: &dyn Storage) {
+        let key = format!("{:x}", uuid::Uuid::new_v4().as_u128());
+        assert!(!storage.exists(&key).await.unwrap());
+
+        storage
+            .put(&key, Bytes::from_static(b"0123456789"))
+            .await
+            .unwrap();
+        assert!(storage.exists(&key).await.unwrap());
+        assert_eq!(read_all(storage, &key, 10).await.unwrap(), b"0123456789");
+        assert_eq!(read(storage, &key, 3..7).await, b"3456");
+        assert_eq!(read(storage, &key, 7..10).await, b"789");
+
+        // Same key, same bytes: the second put is harmless
+        storage
+            .put(&key, Bytes::from_static(b"0123456789"))
+            .await
+            .unwrap();
+        assert_eq!(read_all(storage, &key, 10).await.unwrap(), b"0123456789");
+
+        storage.delete(&key).await.unwrap();
+        assert!(!storage.exists(&key).await.unwrap());
+        assert!(storage.get_range(&key, 0..10).await.is_err());
+        // Deleting what isn't there is fine
+        storage.delete(&key).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn local_storage_round_trips() {
+        let dir = scratch();
+        behaves_like_storage(&LocalStorage::new(&dir)).await;
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn local_storage_shards_by_key() {
+        let dir = scratch();
+        let storage = LocalStorage::new(&dir);
+        storage
+            .put("abcdef", Bytes::from_static(b"x"))
+            .await
+            .unwrap();
+        assert!(dir.join("ab").join("cd").join("abcdef").is_file());
+        // The temporary file was renamed into place
+        assert_eq!(std::fs::read_dir(dir.join("ab/cd")).unwrap().count(), 1);
+        storage.put("a", Bytes::from_static(b"y")).await.unwrap();
+        assert!(dir.join("a").is_file());
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn local_ranges_stop_at_the_end() {
+        let dir = scratch();
+        let storage = LocalStorage::new(&dir);
+        storage
+            .put("abcdef", Bytes::from_static(b"0123456789"))
+            .await
+            .unwrap();
+        assert_eq!(read(&storage, "abcdef", 10..10).await, b"");
+        assert_eq!(read(&storage, "abcdef", 8..20).await, b"89");
+        assert_eq!(read(&storage, "abcdef", 15..20).await, b"");
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    /// Set the `AWS_*` variables `S3Storage` reads, e.g. for a local MinIO.
+    #[tokio::test]
+    #[ignore = "needs an S3 bucket at AWS_BUCKET"]
+    async fn s3_storage_round_trips() {
+        let storage = S3Storage {
+            store: AmazonS3Builder::from_env().build().unwrap(),
+            prefix: format!("arcsin-test-{}", uuid::Uuid::new_v4()),
+        };
+        behaves_like_storage(&storage).await;
+    }
+}
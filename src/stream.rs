@@ -0,0 +1,421 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, Method, StatusCode},
+    response::Response,
+};
+use futures_util::stream::{self, StreamExt};
+use serde::Deserialize;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::SystemTime;
+use time::OffsetDateTime;
+use tokio_util::io::StreamReader;
+use uuid::Uuid;
+
+use crate::cache;
+use crate::loudness;
+use crate::range::{self, ByteRange, RangeRequest};
+use crate::storage::{ByteStream, STREAM_CHUNK_SIZE};
+use crate::transcode::{self, Format, Requested};
+use crate::transcode_cache::{CachedFile, TranscodeCache};
+use crate::AppState;
+
+#[derive(Deserialize)]
+pub struct StreamQuery {
+    /// `opus`, `mp3`, `aac`, `wav`, `fmp4` or `original`. Formats we can't encode fall
+    /// back to the original.
+    pub format: Option<String>,
+    /// Bitrate in kbps for lossy output. Not allowed for `wav`; makes `fmp4` AAC.
+    pub bitrate: Option<u32>,
+    /// `track` or `album`: normalize transcoded output with that ReplayGain. The
+    /// original file is always served as is.
+    pub gain: Option<String>,
+}
+
+/// What's needed to serve a track's audio, without the audio itself.
+pub struct StoredTrack {
+    pub content_hash: String,
+    pub mime_type: String,
+    pub size_bytes: i64,
+    pub updated_at: OffsetDateTime,
+    pub true_peak_dbtp: Option<f32>,
+    pub track_gain_db: Option<f32>,
+    pub album_gain_db: Option<f32>,
+    /// Audio still held in `tracks.data` rather than the storage backend.
+    pub legacy: bool,
+}
+
+pub async fn stored_track(state: &AppState, id: Uuid) -> Result<StoredTrack, StatusCode> {
+    sqlx::query_as!(
+        StoredTrack,
+        r#"
+        SELECT content_hash, mime_type, size_bytes, updated_at, true_peak_dbtp, track_gain_db,
+               album_gain_db, data IS NOT NULL AS "legacy!"
+        FROM tracks WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_optional(&state.app.db)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// The gain in dB `?gain=track` or `?gain=album` asks for, held under the peak ceiling.
+pub fn requested_gain(gain: Option<&str>, track: &StoredTrack) -> Result<Option<f32>, StatusCode> {
+    let gain = match gain {
+        None => None,
+        Some("track") => track.track_gain_db,
+        Some("album") => track.album_gain_db.or(track.track_gain_db),
+        Some(_) => return Err(StatusCode::BAD_REQUEST),
+    };
+    Ok(gain.map(|gain| {
+        let peak = track.true_peak_dbtp.unwrap_or(0.0);
+        // Hundredths of a dB are plenty, and keep cache keys stable
+        let gain = loudness::limit_gain(f64::from(gain), f64::from(peak));
+        (gain * 100.0).round() as f32 / 100.0
+    }))
+}
+
+/// Where the response body comes from.
+enum Source {
+    /// The stored file, read range by range.
+    Stored {
+        id: Uuid,
+        content_hash: String,
+        legacy: bool,
+    },
+    /// Transcoded output, from the transcode cache.
+    Transcoded(CachedFile),
+}
+
+pub async fn stream_track(
+    Path(id): Path<Uuid>,
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<StreamQuery>,
+    method: Method,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let record = stored_track(&state, id).await?;
+
+    if query.bitrate == Some(0) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let gain = requested_gain(query.gain.as_deref(), &record)?;
+    let target = match transcode::requested(query.format.as_deref()) {
+        Requested::Original => None,
+        // Already in the requested format and no bitrate cap, nothing to do
+        Requested::Transcode(format)
+            if format.matches(&record.mime_type) && query.bitrate.is_none() && gain.is_none() =>
+        {
+            None
+        }
+        Requested::Transcode(format) => {
+            let bitrate = format
+                .bitrate(query.bitrate)
+                .ok_or(StatusCode::BAD_REQUEST)?;
+            // Lossy output with no encoder to make it is as good as an unknown format
+            if bitrate.is_some() && state.app.encoder.is_none() {
+                if !transcode::accepts(&headers, &record.mime_type) {
+                    return Err(StatusCode::NOT_ACCEPTABLE);
+                }
+                None
+            } else {
+                Some((format, bitrate))
+            }
+        }
+        Requested::Unavailable if transcode::accepts(&headers, &record.mime_type) => None,
+        Requested::Unavailable => return Err(StatusCode::NOT_ACCEPTABLE),
+        Requested::Unknown => return Err(StatusCode::BAD_REQUEST),
+    };
+
+    let etag = match target {
+        None => format!("\"{}\"", record.content_hash),
+        Some((format, bitrate)) => format!(
+            "\"{}.{}-{}\"",
+            rendition_source(&record.content_hash, gain),
+            format.extension(),
+            bitrate.map_or("source".to_string(), |b| b.to_string())
+        ),
+    };
+    let last_modified = SystemTime::from(record.updated_at);
+
+    if cache::not_modified(&headers, &etag, Some(last_modified)) {
+        return Ok(cache::not_modified_response(
+            &etag,
+            Some(last_modified),
+            cache::AUDIO_CACHE_CONTROL,
+        ));
+    }
+
+    let (source, mime_type, len) = match target {
+        None => (
+            Source::Stored {
+                id,
+                content_hash: record.content_hash,
+                legacy: record.legacy,
+            },
+            record.mime_type,
+            record.size_bytes as u64,
+        ),
+        Some((format, bitrate)) => {
+            let output = transcode_track(&state, id, &record, format, bitrate, gain).await?;
+            let len = output.len;
+            (
+                Source::Transcoded(output),
+                format.mime_type().to_string(),
+                len,
+            )
+        }
+    };
+
+    // A stale If-Range means the client's partial copy is useless, send everything
+    let if_range_ok = headers
+        .get(header::IF_RANGE)
+        .and_then(|h| h.to_str().ok())
+        .is_none_or(|v| range::if_range_matches(v, &etag, last_modified));
+    let request = if if_range_ok {
+        range::parse(
+            headers.get(header::RANGE).and_then(|h| h.to_str().ok()),
+            len,
+        )
+    } else {
+        RangeRequest::Full
+    };
+
+    let builder = Response::builder()
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::ETAG, &etag)
+        .header(header::CACHE_CONTROL, cache::AUDIO_CACHE_CONTROL)
+        .header(header::VARY, header::ACCEPT)
+        .header(
+            header::LAST_MODIFIED,
+            httpdate::fmt_http_date(last_modified),
+        );
+    let head = method == Method::HEAD;
+
+    let response = match request {
+        RangeRequest::Unsatisfiable => builder
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header(header::CONTENT_RANGE, format!("bytes */{}", len))
+            .body(Body::empty()),
+        RangeRequest::Full => {
+            let body = if head || len == 0 {
+                Body::empty()
+            } else {
+                Body::from_stream(source.read(&state, 0..len).await?)
+            };
+            builder
+                .header(header::CONTENT_TYPE, &mime_type)
+                .header(header::CONTENT_LENGTH, len)
+                .body(body)
+        }
+        RangeRequest::Partial(ranges) if ranges.len() == 1 => {
+            let range = ranges[0];
+            let body = if head {
+                Body::empty()
+            } else {
+                Body::from_stream(source.read(&state, range.start..range.end + 1).await?)
+            };
+            builder
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(header::CONTENT_TYPE, &mime_type)
+                .header(header::CONTENT_RANGE, range.content_range(len))
+                .header(header::CONTENT_LENGTH, range.len())
+                .body(body)
+        }
+        RangeRequest::Partial(ranges) => {
+            let boundary = format!("arcsin-{}", Uuid::new_v4().simple());
+            let (parts, length) = multipart_parts(&ranges, len, &mime_type, &boundary);
+
+            let body = if head {
+                Body::empty()
+            } else {
+                let mut streams: Vec<ByteStream> = Vec::with_capacity(parts.len() * 2 + 1);
+                for (part_header, range) in parts.into_iter().zip(&ranges) {
+                    streams.push(stream::once(async move { Ok(part_header) }).boxed());
+                    streams.push(source.read(&state, range.start..range.end + 1).await?);
+                }
+                let closing = Bytes::from(format!("\r\n--{}--\r\n", boundary));
+                streams.push(stream::once(async move { Ok(closing) }).boxed());
+                Body::from_stream(stream::iter(streams).flatten())
+            };
+            builder
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(
+                    header::CONTENT_TYPE,
+                    format!("multipart/byteranges; boundary={}", boundary),
+                )
+                .header(header::CONTENT_LENGTH, length)
+                .body(body)
+        }
+    };
+
+    response.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+impl Source {
+    async fn read(&self, state: &AppState, range: Range<u64>) -> Result<ByteStream, StatusCode> {
+        match self {
+            Source::Stored {
+                id,
+                content_hash,
+                legacy,
+            } => track_stream(state, *id, content_hash, *legacy, range).await,
+            Source::Transcoded(output) => output.stream(range).map_err(|e| {
+                eprintln!("Error reading transcode: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            }),
+        }
+    }
+}
+
+/// Copies the whole stored file to `path`.
+pub async fn spool_track(
+    state: &AppState,
+    id: Uuid,
+    track: &StoredTrack,
+    path: &std::path::Path,
+) -> anyhow::Result<()> {
+    let len = track.size_bytes as u64;
+    let stream = track_stream(state, id, &track.content_hash, track.legacy, 0..len)
+        .await
+        .map_err(|_| anyhow::anyhow!("Failed to read track"))?;
+    let mut file = tokio::fs::File::create(path).await?;
+    tokio::io::copy(&mut StreamReader::new(stream), &mut file).await?;
+    Ok(())
+}
+
+/// Names what a rendition was made from: the content, and the gain applied to it.
+fn rendition_source(content_hash: &str, gain_db: Option<f32>) -> String {
+    match gain_db {
+        Some(gain) => format!("{}{:+.2}dB", content_hash, gain),
+        None => content_hash.to_string(),
+    }
+}
+
+/// Transcoded output for the track, from the transcode cache or encoded into it.
+/// `bitrate` is as given by [`Format::bitrate`].
+pub async fn transcode_track(
+    state: &AppState,
+    id: Uuid,
+    track: &StoredTrack,
+    format: Format,
+    bitrate: Option<u32>,
+    gain_db: Option<f32>,
+) -> Result<CachedFile, StatusCode> {
+    let source = rendition_source(&track.content_hash, gain_db);
+    let key = TranscodeCache::key(&source, format.extension(), bitrate);
+    let produce = |path: PathBuf| async move {
+        // Decoders and ffmpeg both need to seek, so the source goes to disk first
+        let source = PathBuf::from(format!("{}.source", path.display()));
+        let result = match spool_track(state, id, track, &source).await {
+            Ok(()) => {
+                transcode::transcode(
+                    &source,
+                    &track.mime_type,
+                    &path,
+                    format,
+                    bitrate,
+                    gain_db,
+                    state.app.encoder.as_ref(),
+                )
+                .await
+            }
+            Err(e) => Err(e),
+        };
+        tokio::fs::remove_file(&source).await.ok();
+        result
+    };
+
+    state
+        .app
+        .transcodes
+        .get_or_insert_with(&key, produce)
+        .await
+        .map_err(|e| {
+            eprintln!("Error transcoding track {}: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+/// Per-part headers of a `multipart/byteranges` body, and the total body length
+/// including the closing delimiter.
+fn multipart_parts(
+    ranges: &[ByteRange],
+    len: u64,
+    mime_type: &str,
+    boundary: &str,
+) -> (Vec<Bytes>, u64) {
+    let mut total = 0;
+    let parts = ranges
+        .iter()
+        .map(|range| {
+            let part = format!(
+                "\r\n--{}\r\n{}: {}\r\n{}: {}\r\n\r\n",
+                boundary,
+                header::CONTENT_TYPE,
+                mime_type,
+                header::CONTENT_RANGE,
+                range.content_range(len)
+            );
+            total += part.len() as u64 + range.len();
+            Bytes::from(part)
+        })
+        .collect();
+    total += format!("\r\n--{}--\r\n", boundary).len() as u64;
+    (parts, total)
+}
+
+/// Streams `range` of a track's audio. Rows that still keep their bytes in `tracks.data`
+/// have that slice read out of Postgres a chunk at a time.
+async fn track_stream(
+    state: &AppState,
+    id: Uuid,
+    content_hash: &str,
+    legacy: bool,
+    range: Range<u64>,
+) -> Result<ByteStream, StatusCode> {
+    if legacy {
+        // substring() only takes int4 offsets; bytea can't reach past 1 GiB anyway
+        let end = i32::try_from(range.end).map_err(|_| StatusCode::RANGE_NOT_SATISFIABLE)?;
+        let start = range.start as i32;
+        let db = state.app.db.clone();
+        let chunks = stream::try_unfold(start, move |pos| {
+            let db = db.clone();
+            async move {
+                if pos >= end {
+                    return Ok(None);
+                }
+                let len = (end - pos).min(STREAM_CHUNK_SIZE as i32);
+                let chunk = sqlx::query_scalar!(
+                    r#"SELECT substring(data FROM $2 FOR $3) AS "data!" FROM tracks WHERE id = $1"#,
+                    id,
+                    pos + 1,
+                    len
+                )
+                .fetch_one(&db)
+                .await
+                .map_err(std::io::Error::other)?;
+                if chunk.is_empty() {
+                    return Err(std::io::ErrorKind::UnexpectedEof.into());
+                }
+                let next = pos + chunk.len() as i32;
+                Ok(Some((Bytes::from(chunk), next)))
+            }
+        });
+        return Ok(chunks.boxed());
+    }
+
+    state
+        .app
+        .storage
+        .get_range(content_hash, range)
+        .await
+        .map_err(|e| {
+            eprintln!("Error reading track {} from storage: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
@@ -0,0 +1,547 @@
+//! Server-side transcoding for `/api/stream/:id?format=&bitrate=&gain=`.
+//!
+//! Lossy output (Opus, MP3 and AAC, plus the AAC fragments of the HLS bitrate ladder) is
+//! encoded by an `ffmpeg` binary, since there are no pure-Rust encoders for them. Without
+//! one those formats fall back to the original file. Lossless output is decoded by
+//! symphonia and written here: WAV, which plays everywhere (including Safari), or FLAC
+//! in fragmented MP4 for HLS. Sources are read from and output written to disk, so
+//! neither has to fit in memory.
+
+use axum::http::{header, HeaderMap};
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{CodecParameters, DecoderOptions};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader};
+use symphonia::core::io::{MediaSource, MediaSourceStream};
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+use crate::fmp4;
+
+/// Lossy bitrate when none is asked for, in kbps.
+pub const DEFAULT_BITRATE: u32 = 160;
+/// Range of lossy bitrates clients may ask for, in kbps.
+pub const MIN_BITRATE: u32 = 32;
+pub const MAX_BITRATE: u32 = 320;
+const BITS_PER_SAMPLE: u32 = 16;
+const WAV_HEADER_LEN: u32 = 44;
+
+/// Formats clients may ask for but that we have no encoder for.
+const UNAVAILABLE_FORMATS: &[&str] = &["ogg", "vorbis", "flac"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Wav,
+    /// Fragmented MP4 split into HLS segments: FLAC, or AAC when given a bitrate.
+    Fmp4,
+    /// Opus in Ogg.
+    Opus,
+    Mp3,
+    /// AAC in MP4 (`.m4a`).
+    Aac,
+}
+
+impl Format {
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            Format::Wav => "audio/wav",
+            Format::Fmp4 | Format::Aac => "audio/mp4",
+            Format::Opus => "audio/ogg",
+            Format::Mp3 => "audio/mpeg",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Wav => "wav",
+            Format::Fmp4 => "mp4",
+            Format::Opus => "opus",
+            Format::Mp3 => "mp3",
+            Format::Aac => "m4a",
+        }
+    }
+
+    /// Whether a file of `mime_type` already is this format, so no transcode is needed.
+    pub fn matches(&self, mime_type: &str) -> bool {
+        match self {
+            Format::Wav => matches!(mime_type, "audio/wav" | "audio/x-wav" | "audio/wave"),
+            Format::Mp3 => mime_type == "audio/mpeg",
+            // Imported MP4s are never FLAC fragments, and may be ALAC; Ogg may be Vorbis
+            Format::Fmp4 | Format::Aac | Format::Opus => false,
+        }
+    }
+
+    /// The bitrate to encode at, given the one asked for: lossy formats always have one,
+    /// WAV never does, and fMP4 is AAC with one and FLAC without. `None` if it's not
+    /// a bitrate this format can take.
+    pub fn bitrate(&self, requested: Option<u32>) -> Option<Option<u32>> {
+        let valid = requested.is_none_or(|b| (MIN_BITRATE..=MAX_BITRATE).contains(&b));
+        match self {
+            _ if !valid => None,
+            Format::Wav => requested.is_none().then_some(None),
+            Format::Fmp4 => Some(requested),
+            Format::Opus | Format::Mp3 | Format::Aac => {
+                Some(Some(requested.unwrap_or(DEFAULT_BITRATE)))
+            }
+        }
+    }
+}
+
+/// What a `format` query parameter asks for.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Requested {
+    Original,
+    Transcode(Format),
+    /// A real format we can't encode to; serve the original if the client takes it.
+    Unavailable,
+    Unknown,
+}
+
+pub fn requested(format: Option<&str>) -> Requested {
+    let Some(format) = format.map(str::to_ascii_lowercase) else {
+        return Requested::Original;
+    };
+    match format.as_str() {
+        "" | "original" => Requested::Original,
+        "wav" => Requested::Transcode(Format::Wav),
+        "fmp4" => Requested::Transcode(Format::Fmp4),
+        "opus" => Requested::Transcode(Format::Opus),
+        "mp3" => Requested::Transcode(Format::Mp3),
+        "aac" | "m4a" => Requested::Transcode(Format::Aac),
+        other if UNAVAILABLE_FORMATS.contains(&other) => Requested::Unavailable,
+        _ => Requested::Unknown,
+    }
+}
+/// Whether the `Accept` header allows `mime_type`. No header accepts anything.
+pub fn accepts(headers: &HeaderMap, mime_type: &str) -> bool {
+    let Some(accept) = headers.get(header::ACCEPT).and_then(|h| h.to_str().ok()) else {
+        return true;
+    };
+    let major = mime_type.split('/').next().unwrap_or_default();
+    accept.split(',').any(|entry| {
+        let mut params = entry.split(';').map(str::trim);
+        let media = params.next().unwrap_or_default();
+        let refused = params.any(|p| {
+            p.strip_prefix("q=")
+                .and_then(|q| q.parse::<f32>().ok())
+                .is_some_and(|q| q == 0.0)
+        });
+        let matches = media == "*/*"
+            || media.eq_ignore_ascii_case(mime_type)
+            || media
+                .strip_suffix("/*")
+                .is_some_and(|m| m.eq_ignore_ascii_case(major));
+        matches && !refused
+    })
+}
+
+/// The `ffmpeg` binary that does the lossy encoding.
+pub struct Encoder {
+    program: PathBuf,
+}
+
+impl Encoder {
+    /// Reads `FFMPEG` (default `ffmpeg`, from the `PATH`). `None` if it doesn't run.
+    pub fn from_env() -> Option<Self> {
+        let program = PathBuf::from(std::env::var("FFMPEG").unwrap_or_else(|_| "ffmpeg".into()));
+        let runs = std::process::Command::new(&program)
+            .arg("-version")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success());
+        runs.then_some(Self { program })
+    }
+
+    /// Encodes the audio file at `source` to `out`. Dropping the future kills ffmpeg.
+    async fn encode(
+        &self,
+        source: &Path,
+        out: &Path,
+        format: Format,
+        bitrate_kbps: u32,
+        gain_db: Option<f32>,
+    ) -> anyhow::Result<()> {
+        let mut command = tokio::process::Command::new(&self.program);
+        command
+            .args(["-hide_banner", "-loglevel", "error", "-nostdin", "-y", "-i"])
+            .arg(source)
+            .args(["-map", "0:a:0"]);
+        if let Some(gain) = gain_db {
+            command.arg("-af").arg(format!("volume={:.2}dB", gain));
+        }
+        command.arg("-b:a").arg(format!("{}k", bitrate_kbps));
+        let fragment_us = (fmp4::SEGMENT_SECONDS * 1_000_000.0).to_string();
+        match format {
+            Format::Opus => command.args(["-c:a", "libopus", "-f", "ogg"]),
+            Format::Mp3 => command.args(["-c:a", "libmp3lame", "-f", "mp3"]),
+            // Written to a file, so the index can go up front
+            Format::Aac => command.args(["-c:a", "aac", "-f", "mp4", "-movflags", "+faststart"]),
+            Format::Fmp4 => command
+                .args(["-c:a", "aac", "-f", "mp4"])
+                .args(["-movflags", "+empty_moov+default_base_moof"])
+                .args(["-frag_duration", &fragment_us]),
+            Format::Wav => anyhow::bail!("WAV isn't a lossy format"),
+        };
+        let output = command
+            .arg(out)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .output()
+            .await?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "ffmpeg failed ({}): {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Transcodes the audio file at `source` to `format`, written to `out`. A `bitrate_kbps`
+/// (see [`Format::bitrate`]) means lossy output, which needs the `encoder`. `gain_db`
+/// scales the audio, for ReplayGain normalization.
+pub async fn transcode(
+    source: &Path,
+    mime_type: &str,
+    out: &Path,
+    format: Format,
+    bitrate_kbps: Option<u32>,
+    gain_db: Option<f32>,
+    encoder: Option<&Encoder>,
+) -> anyhow::Result<()> {
+    if let Some(bitrate) = bitrate_kbps {
+        let encoder = encoder.ok_or_else(|| anyhow::anyhow!("No encoder for lossy output"))?;
+        return encoder.encode(source, out, format, bitrate, gain_db).await;
+    }
+
+    let (source, out, mime_type) = (source.to_owned(), out.to_owned(), mime_type.to_owned());
+    tokio::task::spawn_blocking(move || {
+        let source = std::fs::File::open(source)?;
+        let out = BufWriter::new(std::fs::File::create(out)?);
+        encode_lossless(source, &mime_type, out, format, gain_db)
+    })
+    .await?
+}
+
+/// Decodes `source` and writes it as WAV or FLAC fMP4 to `out`, a frame at a time.
+fn encode_lossless<W: Write + Seek>(
+    source: impl MediaSource + 'static,
+    mime_type: &str,
+    out: W,
+    format: Format,
+    gain_db: Option<f32>,
+) -> anyhow::Result<()> {
+    let scale = gain_db.map_or(1.0, |gain| 10f32.powf(gain / 20.0));
+    let mut sink = None;
+    let mut error = None;
+    visit_frames(source, mime_type, |rate, channels, _| {
+        let mut sink = match Sink::new(format, out, rate, channels) {
+            Ok(new) => Some(sink.insert(new)),
+            Err(e) => {
+                error = Some(e);
+                None
+            }
+        };
+        let error = &mut error;
+        let mut frame = Vec::with_capacity(channels as usize);
+        move |input: &[f32]| {
+            let Some(out) = sink.as_mut() else {
+                return;
+            };
+            frame.clear();
+            frame.extend(
+                input
+                    .iter()
+                    .take(channels as usize)
+                    .map(|s| to_i16(s * scale)),
+            );
+            if let Err(e) = out.push(&frame) {
+                *error = Some(e);
+                sink = None;
+            }
+        }
+    })?;
+    if let Some(e) = error {
+        return Err(e.into());
+    }
+    sink.ok_or_else(|| anyhow::anyhow!("No audio decoded"))?
+        .finish()?;
+    Ok(())
+}
+
+/// Where [`encode_lossless`] writes its frames.
+enum Sink<W: Write + Seek> {
+    Wav(WavWriter<W>),
+    Fmp4(fmp4::Writer<W>),
+}
+
+impl<W: Write + Seek> Sink<W> {
+    fn new(format: Format, out: W, rate: u32, channels: u32) -> std::io::Result<Self> {
+        match format {
+            Format::Wav => Ok(Sink::Wav(WavWriter::new(out, rate, channels)?)),
+            Format::Fmp4 => Ok(Sink::Fmp4(fmp4::Writer::new(out, rate, channels)?)),
+            _ => Err(std::io::Error::other(format!(
+                "{:?} isn't lossless",
+                format
+            ))),
+        }
+    }
+
+    fn push(&mut self, frame: &[i16]) -> std::io::Result<()> {
+        match self {
+            Sink::Wav(writer) => writer.push(frame),
+            Sink::Fmp4(writer) => writer.push(frame),
+        }
+    }
+
+    fn finish(self) -> std::io::Result<()> {
+        let mut out = match self {
+            Sink::Wav(writer) => writer.finish()?,
+            Sink::Fmp4(writer) => writer.finish()?,
+        };
+        out.flush()
+    }
+}
+
+fn probe(
+    source: impl MediaSource + 'static,
+    mime_type: &str,
+) -> anyhow::Result<Box<dyn FormatReader>> {
+    let mss = MediaSourceStream::new(Box::new(source), Default::default());
+    let mut hint = Hint::new();
+    hint.mime_type(mime_type);
+
+    let probed = symphonia::default::get_probe().format(
+        &hint,
+        mss,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+    Ok(probed.format)
+}
+
+fn track_layout(params: &CodecParameters) -> anyhow::Result<(u32, u32)> {
+    let rate = params
+        .sample_rate
+        .ok_or_else(|| anyhow::anyhow!("Unknown sample rate"))?;
+    let channels = params.channels.map_or(2, |c| c.count() as u32).clamp(1, 2);
+    Ok((rate, channels))
+}
+
+/// Decodes the whole of `source` at its own sample rate. `start` is given the source
+/// layout and its length in frames, if the container says, and returns a visitor, which
+/// then sees every frame as decoded: all of the stream's channels, unclipped.
+pub fn visit_frames<V: FnMut(&[f32])>(
+    source: impl MediaSource + 'static,
+    mime_type: &str,
+    start: impl FnOnce(u32, u32, Option<u64>) -> V,
+) -> anyhow::Result<()> {
+    let mut reader = probe(source, mime_type)?;
+    let track = reader
+        .default_track()
+        .ok_or_else(|| anyhow::anyhow!("No audio track"))?;
+    let track_id = track.id;
+    let mut decoder =
+        symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+
+    let (rate, channels) = track_layout(&track.codec_params)?;
+    let mut visit = start(rate, channels, track.codec_params.n_frames);
+
+    loop {
+        let packet = match reader.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                break
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // A corrupt frame shouldn't end the whole stream
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        let spec = *decoded.spec();
+        let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        buffer.copy_interleaved_ref(decoded);
+        let in_channels = spec.channels.count().max(1);
+
+        for input in buffer.samples().chunks_exact(in_channels) {
+            visit(input);
+        }
+    }
+    Ok(())
+}
+
+/// Streams 16-bit PCM into a WAV file, filling in the sizes once it's finished.
+struct WavWriter<W: Write + Seek> {
+    out: W,
+    data_len: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    fn new(mut out: W, rate: u32, channels: u32) -> std::io::Result<Self> {
+        let block_align = channels * BITS_PER_SAMPLE / 8;
+        out.write_all(b"RIFF")?;
+        out.write_all(&0u32.to_le_bytes())?; // RIFF size, filled in by `finish`
+        out.write_all(b"WAVEfmt ")?;
+        out.write_all(&16u32.to_le_bytes())?;
+        out.write_all(&1u16.to_le_bytes())?; // PCM
+        out.write_all(&(channels as u16).to_le_bytes())?;
+        out.write_all(&rate.to_le_bytes())?;
+        out.write_all(&(rate * block_align).to_le_bytes())?;
+        out.write_all(&(block_align as u16).to_le_bytes())?;
+        out.write_all(&(BITS_PER_SAMPLE as u16).to_le_bytes())?;
+        out.write_all(b"data")?;
+        out.write_all(&0u32.to_le_bytes())?; // data size, likewise
+        Ok(Self { out, data_len: 0 })
+    }
+
+    fn push(&mut self, frame: &[i16]) -> std::io::Result<()> {
+        let len = (frame.len() * 2) as u32;
+        if self.data_len.checked_add(len + WAV_HEADER_LEN).is_none() {
+            return Err(std::io::Error::other("Too long for a WAV file"));
+        }
+        for sample in frame {
+            self.out.write_all(&sample.to_le_bytes())?;
+        }
+        self.data_len += len;
+        Ok(())
+    }
+
+    fn finish(mut self) -> std::io::Result<W> {
+        self.out.seek(SeekFrom::Start(4))?;
+        self.out
+            .write_all(&(WAV_HEADER_LEN - 8 + self.data_len).to_le_bytes())?;
+        self.out
+            .seek(SeekFrom::Start(u64::from(WAV_HEADER_LEN) - 4))?;
+        self.out.write_all(&self.data_len.to_le_bytes())?;
+        self.out.seek(SeekFrom::End(0))?;
+        Ok(self.out)
+    }
+}
+
+/// The inverse of symphonia's conversion, so 16-bit sources come back bit for bit.
+fn to_i16(sample: f32) -> i16 {
+    (sample * 32768.0)
+        .round()
+        .clamp(f32::from(i16::MIN), f32::from(i16::MAX)) as i16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Interleaved 16-bit audio.
+    struct Pcm {
+        rate: u32,
+        channels: u32,
+        samples: Vec<i16>,
+    }
+
+    /// Decodes the whole of `data` into memory, keeping at most two channels.
+    fn decode(data: Vec<u8>, mime_type: &str) -> anyhow::Result<Pcm> {
+        let mut pcm = None;
+        visit_frames(Cursor::new(data), mime_type, |rate, channels, _| {
+            let pcm = pcm.insert(Pcm {
+                rate,
+                channels,
+                samples: Vec::new(),
+            });
+            move |input: &[f32]| {
+                let frame = input.iter().take(pcm.channels as usize);
+                pcm.samples.extend(frame.map(|s| to_i16(*s)));
+            }
+        })?;
+        pcm.ok_or_else(|| anyhow::anyhow!("No audio decoded"))
+    }
+
+    /// A second of a 440 Hz stereo tone at half scale, as WAV.
+    fn tone() -> Vec<u8> {
+        let mut writer = WavWriter::new(Cursor::new(Vec::new()), 8000, 2).unwrap();
+        for i in 0..8000 {
+            let t = i as f32 / 8000.0;
+            let sample = to_i16(0.5 * (2.0 * std::f32::consts::PI * 440.0 * t).sin());
+            writer.push(&[sample, -sample]).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn bitrates_depend_on_the_format() {
+        assert_eq!(Format::Opus.bitrate(None), Some(Some(DEFAULT_BITRATE)));
+        assert_eq!(Format::Mp3.bitrate(Some(128)), Some(Some(128)));
+        assert_eq!(Format::Aac.bitrate(Some(MAX_BITRATE + 1)), None);
+        assert_eq!(Format::Aac.bitrate(Some(MIN_BITRATE - 1)), None);
+        assert_eq!(Format::Wav.bitrate(None), Some(None));
+        assert_eq!(Format::Wav.bitrate(Some(128)), None);
+        assert_eq!(Format::Fmp4.bitrate(None), Some(None));
+        assert_eq!(Format::Fmp4.bitrate(Some(96)), Some(Some(96)));
+    }
+
+    #[test]
+    fn formats_are_parsed() {
+        assert_eq!(requested(None), Requested::Original);
+        assert_eq!(requested(Some("OPUS")), Requested::Transcode(Format::Opus));
+        assert_eq!(requested(Some("m4a")), Requested::Transcode(Format::Aac));
+        assert_eq!(requested(Some("vorbis")), Requested::Unavailable);
+        assert_eq!(requested(Some("midi")), Requested::Unknown);
+    }
+
+    #[test]
+    fn wav_sizes_are_filled_in() {
+        let wav = tone();
+        assert_eq!(wav.len(), WAV_HEADER_LEN as usize + 8000 * 4);
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(wav[4..8], (wav.len() as u32 - 8).to_le_bytes());
+        assert_eq!(wav[40..44], (8000u32 * 4).to_le_bytes());
+    }
+
+    #[test]
+    fn lossless_output_decodes_to_the_source() {
+        let source = decode(tone(), "audio/wav").unwrap();
+        for format in [Format::Wav, Format::Fmp4] {
+            let mut out = Cursor::new(Vec::new());
+            encode_lossless(Cursor::new(tone()), "audio/wav", &mut out, format, None).unwrap();
+            let output = decode(out.into_inner(), format.mime_type()).unwrap();
+            assert_eq!((output.rate, output.channels), (8000, 2));
+            assert_eq!(output.samples, source.samples, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn gain_scales_the_samples() {
+        let source = decode(tone(), "audio/wav").unwrap();
+        let mut out = Cursor::new(Vec::new());
+        encode_lossless(
+            Cursor::new(tone()),
+            "audio/wav",
+            &mut out,
+            Format::Wav,
+            Some(-6.0206),
+        )
+        .unwrap();
+        let output = decode(out.into_inner(), "audio/wav").unwrap();
+        for (a, b) in source.samples.iter().zip(&output.samples) {
+            assert!(
+                (i32::from(*a) / 2 - i32::from(*b)).abs() <= 1,
+                "{} {}",
+                a,
+                b
+            );
+        }
+    }
+}
@@ -0,0 +1,498 @@
+//! Disk cache for transcoded audio, resized artwork and waveform peaks, keyed by content
+//! hash, format and bitrate (or size, or point count).
+//!
+//! The index lives in memory and is rebuilt from the cache directory on startup, using
+//! file modification times (touched on every hit) as the LRU order. Concurrent misses
+//! for the same key wait for a single encode instead of each starting their own.
+//! Entries are written to and served from disk, so a rendition never has to fit in memory.
+
+use axum::body::Bytes;
+use futures_util::stream::{self, StreamExt, TryStreamExt};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::future::Future;
+use std::io::{BufReader, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+use crate::storage::{ByteStream, STREAM_CHUNK_SIZE};
+
+pub struct TranscodeCache {
+    dir: PathBuf,
+    budget: u64,
+    index: Mutex<Index>,
+    /// One lock per key currently being encoded.
+    in_flight: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    coalesced: AtomicU64,
+    evictions: AtomicU64,
+}
+
+#[derive(Default)]
+struct Index {
+    entries: HashMap<String, Entry>,
+    total: u64,
+    tick: u64,
+}
+
+struct Entry {
+    size: u64,
+    last_used: u64,
+}
+
+/// An open cache entry. It stays readable even if it's evicted while being served.
+pub struct CachedFile {
+    file: std::fs::File,
+    pub len: u64,
+}
+
+impl CachedFile {
+    /// Streams `range` of the entry. Streams share the file position, so each only
+    /// seeks once it's first polled: read them one after another, not side by side.
+    pub fn stream(&self, range: Range<u64>) -> std::io::Result<ByteStream> {
+        let mut file = fs::File::from_std(self.file.try_clone()?);
+        let read = async move {
+            file.seek(SeekFrom::Start(range.start)).await?;
+            let reader = file.take(range.end - range.start);
+            std::io::Result::Ok(ReaderStream::with_capacity(reader, STREAM_CHUNK_SIZE))
+        };
+        Ok(stream::once(read).try_flatten().boxed())
+    }
+
+    /// Runs `read` over the entry on the blocking pool, for parsers that need to seek.
+    pub async fn blocking_read<T, F>(&self, read: F) -> anyhow::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(BufReader<std::fs::File>) -> anyhow::Result<T> + Send + 'static,
+    {
+        let mut file = self.file.try_clone()?;
+        tokio::task::spawn_blocking(move || {
+            file.seek(SeekFrom::Start(0))?;
+            read(BufReader::new(file))
+        })
+        .await?
+    }
+
+    /// The whole entry, for small ones like resized artwork.
+    pub async fn read_all(&self) -> std::io::Result<Bytes> {
+        let mut data = Vec::with_capacity(self.len as usize);
+        let mut file = fs::File::from_std(self.file.try_clone()?);
+        file.seek(SeekFrom::Start(0)).await?;
+        file.read_to_end(&mut data).await?;
+        Ok(Bytes::from(data))
+    }
+}
+
+/// Takes a key out of `in_flight` once its encode is over, however it ended.
+struct InFlight<'a> {
+    cache: &'a TranscodeCache,
+    key: &'a str,
+    lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        let mut in_flight = self.cache.in_flight.lock().unwrap();
+        if in_flight
+            .get(self.key)
+            .is_some_and(|l| Arc::ptr_eq(l, &self.lock))
+        {
+            in_flight.remove(self.key);
+        }
+    }
+}
+
+/// A file being written into the cache, deleted unless it makes it in.
+struct PartialFile(Option<PathBuf>);
+
+impl Drop for PartialFile {
+    fn drop(&mut self) {
+        if let Some(path) = self.0.take() {
+            tokio::spawn(async move { fs::remove_file(path).await.ok() });
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub bytes: u64,
+    pub budget_bytes: u64,
+    pub hits: u64,
+    pub misses: u64,
+    /// Requests that waited for another request's encode rather than starting one.
+    pub coalesced: u64,
+    pub evictions: u64,
+    /// Share of lookups that didn't need an encode of their own.
+    pub hit_rate: f64,
+}
+
+impl Index {
+    fn touch(&mut self, key: &str) -> bool {
+        self.tick += 1;
+        match self.entries.get_mut(key) {
+            Some(entry) => {
+                entry.last_used = self.tick;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn insert(&mut self, key: String, size: u64) {
+        self.tick += 1;
+        let entry = Entry {
+            size,
+            last_used: self.tick,
+        };
+        if let Some(old) = self.entries.insert(key, entry) {
+            self.total -= old.size;
+        }
+        self.total += size;
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(old) = self.entries.remove(key) {
+            self.total -= old.size;
+        }
+    }
+
+    /// Least recently used keys to drop until the total fits in `budget`.
+    fn evict(&mut self, budget: u64) -> Vec<String> {
+        let mut evicted = Vec::new();
+        while self.total > budget {
+            let Some(key) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            self.remove(&key);
+            evicted.push(key);
+        }
+        evicted
+    }
+}
+
+impl TranscodeCache {
+    /// Reads `TRANSCODE_CACHE_DIR` (default `cache/transcodes`) and
+    /// `TRANSCODE_CACHE_BYTES` (default 2 GiB).
+    pub fn from_env() -> anyhow::Result<Self> {
+        let dir =
+            std::env::var("TRANSCODE_CACHE_DIR").unwrap_or_else(|_| "cache/transcodes".to_string());
+        let budget = std::env::var("TRANSCODE_CACHE_BYTES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(2 * 1024 * 1024 * 1024);
+        Self::open(dir, budget)
+    }
+
+    pub fn open(dir: impl Into<PathBuf>, budget: u64) -> anyhow::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            let path = entry.path();
+            // Leftovers from an encode that was interrupted mid-write
+            if name.contains(".tmp-") {
+                std::fs::remove_file(&path).ok();
+                continue;
+            }
+            let meta = entry.metadata()?;
+            if meta.is_file() {
+                let used = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                files.push((used, name, meta.len()));
+            }
+        }
+        files.sort();
+
+        let mut index = Index::default();
+        for (_, name, size) in files {
+            index.insert(name, size);
+        }
+        for key in index.evict(budget) {
+            std::fs::remove_file(dir.join(key)).ok();
+        }
+
+        Ok(Self {
+            dir,
+            budget,
+            index: Mutex::new(index),
+            in_flight: Mutex::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            coalesced: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        })
+    }
+
+    /// Cache key for one rendition of some content.
+    pub fn key(content_hash: &str, extension: &str, bitrate: Option<u32>) -> String {
+        match bitrate {
+            Some(bitrate) => format!("{}-{}.{}", content_hash, bitrate, extension),
+            None => format!("{}.{}", content_hash, extension),
+        }
+    }
+
+    /// Returns the cache entry for `key`. On a miss, `produce` is given a path to write
+    /// the entry to, which is added to the cache once it returns.
+    pub async fn get_or_insert_with<F, Fut>(
+        &self,
+        key: &str,
+        produce: F,
+    ) -> anyhow::Result<CachedFile>
+    where
+        F: FnOnce(PathBuf) -> Fut,
+        Fut: Future<Output = anyhow::Result<()>>,
+    {
+        if let Some(file) = self.lookup(key).await {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(file);
+        }
+
+        let (flight, waited) = {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            let (lock, waited) = match in_flight.get(key) {
+                Some(lock) => (lock.clone(), true),
+                None => {
+                    let lock = Arc::new(tokio::sync::Mutex::new(()));
+                    in_flight.insert(key.to_string(), lock.clone());
+                    (lock, false)
+                }
+            };
+            let flight = InFlight {
+                cache: self,
+                key,
+                lock,
+            };
+            (flight, waited)
+        };
+        let _guard = flight.lock.lock().await;
+
+        // Whoever held the lock before us may have just filled the cache
+        if waited {
+            if let Some(file) = self.lookup(key).await {
+                self.coalesced.fetch_add(1, Ordering::Relaxed);
+                return Ok(file);
+            }
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let tmp = self
+            .dir
+            .join(format!("{}.tmp-{}", key, uuid::Uuid::new_v4()));
+        let mut partial = PartialFile(Some(tmp.clone()));
+        produce(tmp.clone()).await?;
+        let file = self.insert(key, &tmp).await?;
+        partial.0 = None;
+        Ok(file)
+    }
+
+    /// Like [`get_or_insert_with`](Self::get_or_insert_with), for small entries that
+    /// are made and wanted whole.
+    pub async fn get_or_insert_bytes<F, Fut>(&self, key: &str, produce: F) -> anyhow::Result<Bytes>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = anyhow::Result<Bytes>>,
+    {
+        let file = self
+            .get_or_insert_with(key, |path| async move {
+                fs::write(path, produce().await?).await?;
+                Ok(())
+            })
+            .await?;
+        Ok(file.read_all().await?)
+    }
+
+    async fn lookup(&self, key: &str) -> Option<CachedFile> {
+        if !self.index.lock().unwrap().touch(key) {
+            return None;
+        }
+        match open_file(&self.dir.join(key)).await {
+            Ok(file) => {
+                // Keeps the LRU order across restarts
+                if let Ok(clone) = file.file.try_clone() {
+                    tokio::task::spawn_blocking(move || clone.set_modified(SystemTime::now()));
+                }
+                Some(file)
+            }
+            Err(_) => {
+                self.index.lock().unwrap().remove(key);
+                None
+            }
+        }
+    }
+
+    /// Moves a finished file at `tmp` into the cache as `key`, making room for it.
+    async fn insert(&self, key: &str, tmp: &Path) -> std::io::Result<CachedFile> {
+        let path = self.dir.join(key);
+        fs::rename(tmp, &path).await?;
+        // Opened before anything is evicted, in case that's this very entry
+        let file = open_file(&path).await?;
+
+        let evicted = {
+            let mut index = self.index.lock().unwrap();
+            index.insert(key.to_string(), file.len);
+            index.evict(self.budget)
+        };
+        for key in evicted {
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+            fs::remove_file(self.dir.join(key)).await.ok();
+        }
+        Ok(file)
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let index = self.index.lock().unwrap();
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        let coalesced = self.coalesced.load(Ordering::Relaxed);
+        let lookups = hits + misses + coalesced;
+        CacheStats {
+            entries: index.entries.len(),
+            bytes: index.total,
+            budget_bytes: self.budget,
+            hits,
+            misses,
+            coalesced,
+            evictions: self.evictions.load(Ordering::Relaxed),
+            hit_rate: if lookups == 0 {
+                0.0
+            } else {
+                (hits + coalesced) as f64 / lookups as f64
+            },
+        }
+    }
+}
+
+async fn open_file(path: &Path) -> std::io::Result<CachedFile> {
+    let file = fs::File::open(path).await?;
+    let len = file.metadata().await?.len();
+    Ok(CachedFile {
+        file: file.into_std().await,
+        len,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn scratch() -> PathBuf {
+        std::env::temp_dir().join(format!("arcsin-cache-{}", uuid::Uuid::new_v4()))
+    }
+
+    async fn collect(stream: ByteStream) -> Vec<u8> {
+        stream
+            .try_fold(Vec::new(), |mut data, chunk| async move {
+                data.extend_from_slice(&chunk);
+                Ok(data)
+            })
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn entries_are_streamed_by_range() {
+        let dir = scratch();
+        let cache = TranscodeCache::open(&dir, 1024).unwrap();
+        let produce = |path| async move {
+            fs::write(path, b"0123456789").await?;
+            Ok(())
+        };
+        let file = cache.get_or_insert_with("key", produce).await.unwrap();
+        assert_eq!(file.len, 10);
+
+        // Read one after another, out of order
+        let late = file.stream(6..10).unwrap();
+        let early = file.stream(1..4).unwrap();
+        assert_eq!(collect(early).await, b"123");
+        assert_eq!(collect(late).await, b"6789");
+
+        let hit = cache
+            .get_or_insert_with("key", |_| async { panic!("not a miss") })
+            .await
+            .unwrap();
+        assert_eq!(hit.read_all().await.unwrap(), &b"0123456789"[..]);
+        assert_eq!(cache.stats().hits, 1);
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn concurrent_misses_share_one_encode() {
+        const CALLERS: u64 = 8;
+        let dir = scratch();
+        let cache = TranscodeCache::open(&dir, 1024).unwrap();
+        let produced = AtomicU64::new(0);
+        let produce = || async {
+            produced.fetch_add(1, Ordering::Relaxed);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Ok(Bytes::from_static(b"encoded"))
+        };
+        let callers = (0..CALLERS).map(|_| cache.get_or_insert_bytes("key", produce));
+        let results = futures_util::future::join_all(callers).await;
+
+        assert_eq!(produced.load(Ordering::Relaxed), 1);
+        for data in results {
+            assert_eq!(data.unwrap(), &b"encoded"[..]);
+        }
+        let stats = cache.stats();
+        assert_eq!(stats.misses, 1);
+        assert_eq!(stats.coalesced, CALLERS - 1);
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn entries_over_budget_are_still_served() {
+        let dir = scratch();
+        let cache = TranscodeCache::open(&dir, 4).unwrap();
+        let produce = |path| async move {
+            fs::write(path, b"too big").await?;
+            Ok(())
+        };
+        let file = cache.get_or_insert_with("key", produce).await.unwrap();
+        assert_eq!(file.read_all().await.unwrap(), &b"too big"[..]);
+        assert_eq!(cache.stats().entries, 0);
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn cancelled_encodes_leave_nothing_behind() {
+        let dir = scratch();
+        let cache = TranscodeCache::open(&dir, 1024).unwrap();
+        let produce = |path| async move {
+            fs::write(path, b"partial").await?;
+            std::future::pending::<()>().await;
+            Ok(())
+        };
+        let encode = cache.get_or_insert_with("key", produce);
+        assert!(tokio::time::timeout(Duration::from_millis(50), encode)
+            .await
+            .is_err());
+        assert!(cache.in_flight.lock().unwrap().is_empty());
+
+        // The partial file is removed in the background
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+        let failed = cache
+            .get_or_insert_with("key", |_| async { anyhow::bail!("no") })
+            .await;
+        assert!(failed.is_err());
+        assert!(cache.in_flight.lock().unwrap().is_empty());
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
@@ -0,0 +1,408 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{Multipart, Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use futures_util::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::path::{Path as FsPath, PathBuf};
+use std::sync::Arc;
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use uuid::Uuid;
+
+use crate::app::{ImportPolicy, ImportResult, ImportStatus};
+use crate::auth::Claims;
+use crate::scan;
+use crate::AppState;
+
+const UPLOAD_OFFSET: &str = "upload-offset";
+
+pub enum UploadError {
+    MissingFile,
+    InvalidFilename,
+    UnsupportedType,
+    TooLarge,
+    MissingOffset,
+    OffsetMismatch(i64),
+    NotFound,
+    Internal,
+}
+
+impl IntoResponse for UploadError {
+    fn into_response(self) -> Response {
+        let (status, error_message) = match self {
+            UploadError::MissingFile => (StatusCode::BAD_REQUEST, "Missing file field"),
+            UploadError::InvalidFilename => (StatusCode::BAD_REQUEST, "Invalid filename"),
+            UploadError::UnsupportedType => (
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "File is not a supported audio format",
+            ),
+            UploadError::TooLarge => (StatusCode::PAYLOAD_TOO_LARGE, "Upload too large"),
+            UploadError::MissingOffset => (StatusCode::BAD_REQUEST, "Missing Upload-Offset header"),
+            UploadError::OffsetMismatch(offset) => {
+                let body = Json(serde_json::json!({
+                    "error": "Upload-Offset does not match",
+                    "offset": offset,
+                }));
+                return (
+                    StatusCode::CONFLICT,
+                    [(UPLOAD_OFFSET, offset.to_string())],
+                    body,
+                )
+                    .into_response();
+            }
+            UploadError::NotFound => (StatusCode::NOT_FOUND, "Upload not found"),
+            UploadError::Internal => (StatusCode::INTERNAL_SERVER_ERROR, "Upload failed"),
+        };
+        let body = Json(serde_json::json!({
+            "error": error_message,
+        }));
+        (status, body).into_response()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreateUploadPayload {
+    pub filename: String,
+    pub size: i64,
+}
+
+#[derive(Serialize)]
+pub struct UploadStatus {
+    pub id: Uuid,
+    pub filename: String,
+    pub size: i64,
+    pub offset: i64,
+    /// Set once the last chunk arrived and the track went through the importer.
+    pub result: Option<ImportResult>,
+}
+
+fn upload_dir() -> PathBuf {
+    PathBuf::from(std::env::var("UPLOAD_DIR").unwrap_or_else(|_| "uploads".to_string()))
+}
+
+fn max_upload_bytes() -> i64 {
+    std::env::var("MAX_UPLOAD_BYTES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(512 * 1024 * 1024)
+}
+
+/// How long an unfinished upload may go without a chunk before it's removed.
+fn upload_ttl_hours() -> i32 {
+    std::env::var("UPLOAD_TTL_HOURS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(24)
+}
+
+/// Deletes uploads left idle past `UPLOAD_TTL_HOURS`, partial files included. An upload
+/// taking a chunk holds its row lock, so it is never removed mid-append.
+pub async fn expire_stale_uploads(db: &PgPool) -> anyhow::Result<u64> {
+    let ids = sqlx::query_scalar!(
+        "DELETE FROM uploads WHERE updated_at < NOW() - make_interval(hours => $1) RETURNING id",
+        upload_ttl_hours()
+    )
+    .fetch_all(db)
+    .await?;
+    for id in &ids {
+        fs::remove_dir_all(upload_dir().join(id.to_string()))
+            .await
+            .ok();
+    }
+    Ok(ids.len() as u64)
+}
+
+/// Keeps only the final path component so clients can't write outside the upload dir.
+fn sanitize_filename(name: &str) -> Result<String, UploadError> {
+    FsPath::new(name.trim())
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .filter(|n| !n.starts_with('.'))
+        .ok_or(UploadError::InvalidFilename)
+}
+
+fn import_status_code(result: &ImportResult) -> StatusCode {
+    match result.status {
+        ImportStatus::New | ImportStatus::FilenameClash => StatusCode::CREATED,
+        ImportStatus::DuplicateContent | ImportStatus::Updated => StatusCode::OK,
+    }
+}
+
+/// Appends `stream` to `file`, failing once more than `limit` bytes would be written.
+async fn write_limited<S, E>(file: &mut File, mut stream: S, limit: i64) -> Result<i64, UploadError>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: std::fmt::Display,
+{
+    let mut written = 0i64;
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| {
+            eprintln!("Error reading upload body: {}", e);
+            UploadError::Internal
+        })?;
+        written += chunk.len() as i64;
+        if written > limit {
+            return Err(UploadError::TooLarge);
+        }
+        file.write_all(&chunk).await.map_err(|e| {
+            eprintln!("Error writing upload: {}", e);
+            UploadError::Internal
+        })?;
+    }
+    file.flush().await.map_err(|_| UploadError::Internal)?;
+    Ok(written)
+}
+
+/// Checks the content really is audio, then runs the file through the normal importer.
+async fn finish_upload(state: &AppState, path: &FsPath) -> Result<ImportResult, UploadError> {
+    let mut head = vec![0u8; 8192];
+    let mut file = File::open(path).await.map_err(|_| UploadError::Internal)?;
+    let n = file
+        .read(&mut head)
+        .await
+        .map_err(|_| UploadError::Internal)?;
+    if scan::sniff_audio(&head[..n]).is_none() {
+        return Err(UploadError::UnsupportedType);
+    }
+
+    let mut result = state
+        .app
+        .import_track(path, &ImportPolicy::Delete)
+        .await
+        .map_err(|e| {
+            eprintln!("Error importing upload {:?}: {}", path, e);
+            UploadError::Internal
+        })?;
+    // The staging directory is ours; the client only knows the filename
+    result.path = path.file_name().map(PathBuf::from).unwrap_or_default();
+    Ok(result)
+}
+
+pub async fn upload_track(
+    State(state): State<Arc<AppState>>,
+    mut multipart: Multipart,
+) -> Result<(StatusCode, Json<ImportResult>), UploadError> {
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|_| UploadError::MissingFile)?
+    {
+        if field.name() != Some("file") {
+            continue;
+        }
+
+        let filename = sanitize_filename(field.file_name().unwrap_or_default())?;
+        let dir = upload_dir().join(Uuid::new_v4().to_string());
+        let path = dir.join(&filename);
+
+        let result = async {
+            fs::create_dir_all(&dir)
+                .await
+                .map_err(|_| UploadError::Internal)?;
+            let mut file = File::create(&path)
+                .await
+                .map_err(|_| UploadError::Internal)?;
+            write_limited(&mut file, field, max_upload_bytes()).await?;
+            finish_upload(&state, &path).await
+        }
+        .await;
+
+        fs::remove_dir_all(&dir).await.ok();
+        let result = result?;
+        return Ok((import_status_code(&result), Json(result)));
+    }
+
+    Err(UploadError::MissingFile)
+}
+
+pub async fn create_upload(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Json(payload): Json<CreateUploadPayload>,
+) -> Result<(StatusCode, Json<UploadStatus>), UploadError> {
+    if payload.size <= 0 {
+        return Err(UploadError::MissingFile);
+    }
+    if payload.size > max_upload_bytes() {
+        return Err(UploadError::TooLarge);
+    }
+    let filename = sanitize_filename(&payload.filename)?;
+    if let Err(e) = expire_stale_uploads(&state.app.db).await {
+        eprintln!("Error expiring stale uploads: {}", e);
+    }
+
+    let id = sqlx::query!(
+        "INSERT INTO uploads (username, filename, size) VALUES ($1, $2, $3) RETURNING id",
+        claims.sub,
+        filename,
+        payload.size
+    )
+    .fetch_one(&state.app.db)
+    .await
+    .map_err(|e| {
+        eprintln!("Error creating upload: {}", e);
+        UploadError::Internal
+    })?
+    .id;
+
+    let dir = upload_dir().join(id.to_string());
+    fs::create_dir_all(&dir)
+        .await
+        .map_err(|_| UploadError::Internal)?;
+    File::create(dir.join(&filename))
+        .await
+        .map_err(|_| UploadError::Internal)?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(UploadStatus {
+            id,
+            filename,
+            size: payload.size,
+            offset: 0,
+            result: None,
+        }),
+    ))
+}
+
+pub async fn get_upload(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<UploadStatus>, UploadError> {
+    let upload = sqlx::query!(
+        "SELECT filename, size FROM uploads WHERE id = $1 AND username = $2",
+        id,
+        claims.sub
+    )
+    .fetch_optional(&state.app.db)
+    .await
+    .map_err(|_| UploadError::Internal)?
+    .ok_or(UploadError::NotFound)?;
+
+    let path = upload_dir().join(id.to_string()).join(&upload.filename);
+    let offset = fs::metadata(&path)
+        .await
+        .map_err(|_| UploadError::NotFound)?
+        .len() as i64;
+
+    Ok(Json(UploadStatus {
+        id,
+        filename: upload.filename,
+        size: upload.size,
+        offset,
+        result: None,
+    }))
+}
+
+/// Appends one chunk. The `Upload-Offset` header must match the bytes received so far,
+/// so a client that lost its connection asks `GET /api/uploads/:id` and resumes from there.
+pub async fn append_upload(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<(StatusCode, Json<UploadStatus>), UploadError> {
+    let client_offset = headers
+        .get(UPLOAD_OFFSET)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.parse::<i64>().ok())
+        .ok_or(UploadError::MissingOffset)?;
+
+    // Row lock serialises concurrent PATCHes for the same upload
+    let mut tx = state
+        .app
+        .db
+        .begin()
+        .await
+        .map_err(|_| UploadError::Internal)?;
+    let upload = sqlx::query!(
+        "SELECT filename, size FROM uploads WHERE id = $1 AND username = $2 FOR UPDATE",
+        id,
+        claims.sub
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|_| UploadError::Internal)?
+    .ok_or(UploadError::NotFound)?;
+
+    let dir = upload_dir().join(id.to_string());
+    let path = dir.join(&upload.filename);
+    let mut file = OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .await
+        .map_err(|_| UploadError::NotFound)?;
+    let offset = file
+        .metadata()
+        .await
+        .map_err(|_| UploadError::Internal)?
+        .len() as i64;
+    if client_offset != offset {
+        return Err(UploadError::OffsetMismatch(offset));
+    }
+
+    let written = write_limited(&mut file, body.into_data_stream(), upload.size - offset).await?;
+    let offset = offset + written;
+    drop(file);
+
+    let mut status = UploadStatus {
+        id,
+        filename: upload.filename,
+        size: upload.size,
+        offset,
+        result: None,
+    };
+
+    if offset < upload.size {
+        sqlx::query!("UPDATE uploads SET updated_at = NOW() WHERE id = $1", id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|_| UploadError::Internal)?;
+        tx.commit().await.map_err(|_| UploadError::Internal)?;
+        return Ok((StatusCode::OK, Json(status)));
+    }
+
+    let result = finish_upload(&state, &path).await;
+    sqlx::query!("DELETE FROM uploads WHERE id = $1", id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| UploadError::Internal)?;
+    tx.commit().await.map_err(|_| UploadError::Internal)?;
+    fs::remove_dir_all(&dir).await.ok();
+
+    let result = result?;
+    let code = import_status_code(&result);
+    status.result = Some(result);
+    Ok((code, Json(status)))
+}
+
+pub async fn delete_upload(
+    State(state): State<Arc<AppState>>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, UploadError> {
+    let deleted = sqlx::query!(
+        "DELETE FROM uploads WHERE id = $1 AND username = $2",
+        id,
+        claims.sub
+    )
+    .execute(&state.app.db)
+    .await
+    .map_err(|_| UploadError::Internal)?;
+
+    if deleted.rows_affected() == 0 {
+        return Err(UploadError::NotFound);
+    }
+    fs::remove_dir_all(upload_dir().join(id.to_string()))
+        .await
+        .ok();
+
+    Ok(StatusCode::NO_CONTENT)
+}
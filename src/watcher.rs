@@ -0,0 +1,157 @@
+use notify_debouncer_full::notify::event::{ModifyKind, RenameMode};
+use notify_debouncer_full::notify::{EventKind, RecursiveMode};
+use notify_debouncer_full::{new_debouncer, DebounceEventResult, DebouncedEvent};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+use crate::app::ImportPolicy;
+use crate::scan::ScanOptions;
+use crate::AppState;
+
+/// Watches library directories and keeps `tracks` in sync with them.
+///
+/// Events are debounced so a large copy settles before anything is imported.
+/// Watched directories are treated as libraries, so files are always kept in place.
+pub fn spawn(
+    state: Arc<AppState>,
+    roots: Vec<PathBuf>,
+    options: ScanOptions,
+    debounce: Duration,
+) -> anyhow::Result<()> {
+    let (tx, mut rx) = mpsc::unbounded_channel::<DebounceEventResult>();
+
+    let mut debouncer = new_debouncer(debounce, None, move |result| {
+        tx.send(result).ok();
+    })?;
+    for root in &roots {
+        debouncer.watch(root, RecursiveMode::Recursive)?;
+        println!("Watching directory: {:?}", root);
+    }
+
+    tokio::spawn(async move {
+        // The debouncer stops watching when dropped, keep it alive with the task
+        let _debouncer = debouncer;
+
+        while let Some(result) = rx.recv().await {
+            match result {
+                Ok(events) => handle_events(&state, &roots, &options, events).await,
+                Err(errors) => {
+                    for e in errors {
+                        eprintln!("Watch error: {}", e);
+                    }
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+async fn handle_events(
+    state: &AppState,
+    roots: &[PathBuf],
+    options: &ScanOptions,
+    events: Vec<DebouncedEvent>,
+) {
+    let mut touched = BTreeSet::new();
+
+    for DebouncedEvent { event, .. } in events {
+        match event.kind {
+            EventKind::Access(_) | EventKind::Modify(ModifyKind::Metadata(_)) => {}
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if event.paths.len() == 2 => {
+                let (from, to) = (&event.paths[0], &event.paths[1]);
+                if let Err(e) = state.app.rename_source(from, to).await {
+                    eprintln!("Failed to follow rename {:?} -> {:?}: {}", from, to, e);
+                }
+                touched.insert(to.clone());
+            }
+            _ => touched.extend(event.paths),
+        }
+    }
+
+    // Decide from the current state of the filesystem rather than the event kind,
+    // a debounced batch may contain create + modify + remove for the same path.
+    for path in touched {
+        let Some(root) = roots.iter().find(|r| path.starts_with(r)) else {
+            continue;
+        };
+
+        if path.is_dir() {
+            match state
+                .app
+                .import_tracks_within(root, &path, options, &ImportPolicy::Keep)
+                .await
+            {
+                Ok(report) => {
+                    for failure in report.failures {
+                        eprintln!("Failed to import {:?}: {}", failure.path, failure.error);
+                    }
+                }
+                Err(e) => eprintln!("Failed to scan {:?}: {}", path, e),
+            }
+        } else if path.is_file() {
+            if !options.matches(root, &path).unwrap_or(false) {
+                continue;
+            }
+            if let Err(e) = state.app.import_track(&path, &ImportPolicy::Keep).await {
+                eprintln!("Failed to import {:?}: {}", path, e);
+            }
+        } else {
+            mark_removed(state, &source_path(root, &path)).await;
+        }
+    }
+}
+
+/// `path` the way the import stored it, canonicalized. A removed path no longer
+/// resolves, so its deepest ancestor under `root` that still does is canonicalized and
+/// the rest joined back on.
+fn source_path(root: &Path, path: &Path) -> PathBuf {
+    for ancestor in path.ancestors().take_while(|a| a.starts_with(root)) {
+        if let Ok(canonical) = std::fs::canonicalize(ancestor) {
+            return match path.strip_prefix(ancestor) {
+                Ok(rest) if !rest.as_os_str().is_empty() => canonical.join(rest),
+                _ => canonical,
+            };
+        }
+    }
+    path.to_path_buf()
+}
+
+async fn mark_removed(state: &AppState, path: &Path) {
+    match state.app.mark_removed(path).await {
+        Ok(0) => {}
+        Ok(n) => println!("Marked {} track(s) under {:?} as removed", n, path),
+        Err(e) => eprintln!("Failed to mark {:?} as removed: {}", path, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removed_paths_resolve_like_imported_ones() {
+        let root = std::env::temp_dir().join(format!("arcsin-watch-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(root.join("real")).unwrap();
+        std::os::unix::fs::symlink(root.join("real"), root.join("link")).unwrap();
+        let root = std::fs::canonicalize(&root).unwrap();
+
+        // What the import would have stored, while the file was there
+        let file = root.join("link/gone.mp3");
+        std::fs::write(&file, b"").unwrap();
+        let imported = std::fs::canonicalize(&file).unwrap();
+        std::fs::remove_file(&file).unwrap();
+        assert_eq!(source_path(&root, &file), imported);
+
+        // Whole directories go too
+        assert_eq!(
+            source_path(&root, &root.join("link/album/01.mp3")),
+            root.join("real/album/01.mp3")
+        );
+        assert_eq!(source_path(&root, &root.join("link")), root.join("real"));
+        std::fs::remove_dir_all(root).ok();
+    }
+}
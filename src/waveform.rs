@@ -0,0 +1,375 @@
+//! Downsampled waveform peaks for scrubbing previews.
+//!
+//! Peaks use the audiowaveform data format (version 2, one channel, 8 bits), which
+//! peaks.js and wavesurfer.js read directly: `?format=dat` is the binary form and the
+//! default is its JSON equivalent. Each track's peaks are computed once, at `MAX_POINTS`
+//! resolution while its frames are decoded, and kept in the transcode cache as `.dat`;
+//! smaller `points` are downsampled from that on request.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::cache;
+use crate::stream::{spool_track, stored_track, StoredTrack};
+use crate::transcode;
+use crate::transcode_cache::TranscodeCache;
+use crate::AppState;
+
+const DEFAULT_POINTS: u32 = 1000;
+const MAX_POINTS: u32 = 20_000;
+const DAT_VERSION: i32 = 2;
+/// Header flag for 8-bit samples.
+const FLAG_8_BIT: u32 = 1;
+const HEADER_LEN: usize = 24;
+
+#[derive(Deserialize)]
+pub struct WaveformQuery {
+    /// Number of min/max pairs; short tracks may get fewer.
+    pub points: Option<u32>,
+    /// `json` (default) or `dat`.
+    pub format: Option<String>,
+}
+
+/// The audiowaveform JSON layout.
+#[derive(Serialize, Debug, PartialEq)]
+struct Waveform {
+    version: i32,
+    channels: u32,
+    sample_rate: u32,
+    samples_per_pixel: u32,
+    bits: u32,
+    length: u32,
+    /// Interleaved min, max pairs.
+    data: Vec<i8>,
+}
+
+/// `GET /api/tracks/:id/waveform?points=1000`
+pub async fn get_waveform(
+    Path(id): Path<Uuid>,
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<WaveformQuery>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let points = query.points.unwrap_or(DEFAULT_POINTS);
+    if points == 0 {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let points = points.min(MAX_POINTS);
+    let binary = match query.format.as_deref() {
+        None | Some("json") => false,
+        Some("dat") => true,
+        Some(_) => return Err(StatusCode::BAD_REQUEST),
+    };
+
+    let track = stored_track(&state, id).await?;
+    let etag = format!("\"{}-{}.dat\"", track.content_hash, points);
+    if binary && cache::not_modified(&headers, &etag, None) {
+        return Ok(cache::not_modified_response(
+            &etag,
+            None,
+            cache::AUDIO_CACHE_CONTROL,
+        ));
+    }
+
+    let key = TranscodeCache::key(&track.content_hash, "dat", None);
+    let state = &state;
+    let track = &track;
+    let produce = |path: PathBuf| async move {
+        // Decoders need to seek, so the source goes to disk first
+        let source = PathBuf::from(format!("{}.source", path.display()));
+        let result = render(state, id, track, &source, path).await;
+        tokio::fs::remove_file(&source).await.ok();
+        result
+    };
+    let cached = state
+        .app
+        .transcodes
+        .get_or_insert_with(&key, produce)
+        .await
+        .map_err(|e| {
+            eprintln!("Error computing waveform for track {}: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    let dat = cached.read_all().await.map_err(|e| {
+        eprintln!("Error reading waveform for track {}: {}", id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let waveform = Waveform::from_dat(&dat)
+        .ok_or_else(|| {
+            eprintln!("Corrupt cached waveform for track {}", id);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .downsample(points);
+
+    if binary {
+        return Ok((
+            [
+                (header::CONTENT_TYPE, "application/octet-stream"),
+                (header::ETAG, etag.as_str()),
+                (header::CACHE_CONTROL, cache::AUDIO_CACHE_CONTROL),
+            ],
+            waveform.to_dat(),
+        )
+            .into_response());
+    }
+    Ok(cache::json(&headers, &waveform))
+}
+
+/// Spools the track to `source` and writes its peaks to `path`.
+async fn render(
+    state: &AppState,
+    id: Uuid,
+    track: &StoredTrack,
+    source: &std::path::Path,
+    path: PathBuf,
+) -> anyhow::Result<()> {
+    spool_track(state, id, track, source).await?;
+    let source = source.to_path_buf();
+    let mime_type = track.mime_type.clone();
+    tokio::task::spawn_blocking(move || {
+        std::fs::write(path, compute(&source, &mime_type)?.to_dat())?;
+        Ok(())
+    })
+    .await?
+}
+
+/// Peaks of the file at `source`, `MAX_POINTS` pairs at most.
+fn compute(source: &std::path::Path, mime_type: &str) -> anyhow::Result<Waveform> {
+    let mut peaks = None;
+    transcode::visit_frames(
+        std::fs::File::open(source)?,
+        mime_type,
+        |rate, channels, frames| {
+            let peaks = peaks.insert(Peaks::new(rate, MAX_POINTS, frames));
+            move |frame: &[f32]| {
+                let frame = &frame[..channels as usize];
+                let mix = frame.iter().sum::<f32>() / channels as f32;
+                peaks.push((mix * 32768.0).round().clamp(-32768.0, 32767.0) as i32);
+            }
+        },
+    )?;
+    peaks
+        .map(Peaks::finish)
+        .ok_or_else(|| anyhow::anyhow!("No audio decoded"))
+}
+
+/// Running min/max of the mono mixdown, one pair per `samples_per_pixel` frames. The
+/// frame count sets `samples_per_pixel` up front; without one it starts at a frame a
+/// pixel and doubles, merging neighbours, whenever the pairs would outgrow `points`.
+struct Peaks {
+    sample_rate: u32,
+    points: usize,
+    samples_per_pixel: u32,
+    buckets: Vec<(i32, i32)>,
+    current: (i32, i32),
+    filled: u32,
+}
+
+impl Peaks {
+    fn new(sample_rate: u32, points: u32, frames: Option<u64>) -> Self {
+        let samples_per_pixel = frames.map_or(1, |frames| frames.div_ceil(points as u64).max(1));
+        Peaks {
+            sample_rate,
+            points: points as usize,
+            samples_per_pixel: u32::try_from(samples_per_pixel).unwrap_or(u32::MAX),
+            buckets: Vec::new(),
+            current: (i32::MAX, i32::MIN),
+            filled: 0,
+        }
+    }
+
+    /// Adds one 16-bit mono sample.
+    fn push(&mut self, sample: i32) {
+        self.current = (self.current.0.min(sample), self.current.1.max(sample));
+        self.filled += 1;
+        if self.filled < self.samples_per_pixel {
+            return;
+        }
+        self.buckets.push(self.current);
+        self.current = (i32::MAX, i32::MIN);
+        self.filled = 0;
+        if self.buckets.len() > self.points {
+            self.widen();
+        }
+    }
+
+    /// Doubles `samples_per_pixel`. An odd bucket out holds half a new pixel, so it
+    /// goes back to being the one being filled.
+    fn widen(&mut self) {
+        if self.buckets.len() % 2 == 1 {
+            self.current = self.buckets.pop().unwrap();
+            self.filled = self.samples_per_pixel;
+        }
+        self.buckets = self.buckets.chunks(2).map(merge).collect();
+        self.samples_per_pixel *= 2;
+    }
+
+    fn finish(mut self) -> Waveform {
+        if self.filled > 0 {
+            self.buckets.push(self.current);
+        }
+        Waveform {
+            version: DAT_VERSION,
+            channels: 1,
+            sample_rate: self.sample_rate,
+            samples_per_pixel: self.samples_per_pixel,
+            bits: 8,
+            length: self.buckets.len() as u32,
+            data: self
+                .buckets
+                .iter()
+                .flat_map(|&(min, max)| [(min >> 8) as i8, (max >> 8) as i8])
+                .collect(),
+        }
+    }
+}
+
+fn merge<T: Ord + Copy>(pairs: &[(T, T)]) -> (T, T) {
+    pairs
+        .iter()
+        .copied()
+        .reduce(|(min, max), (lo, hi)| (min.min(lo), max.max(hi)))
+        .unwrap()
+}
+
+impl Waveform {
+    /// Merges neighbouring pairs until there are `points` at most.
+    fn downsample(self, points: u32) -> Waveform {
+        if self.length <= points {
+            return self;
+        }
+        let factor = self.length.div_ceil(points);
+        let pairs: Vec<(i8, i8)> = self.data.chunks(2).map(|p| (p[0], p[1])).collect();
+        let data: Vec<i8> = pairs
+            .chunks(factor as usize)
+            .map(merge)
+            .flat_map(|(min, max)| [min, max])
+            .collect();
+        Waveform {
+            samples_per_pixel: self.samples_per_pixel.saturating_mul(factor),
+            length: data.len() as u32 / 2,
+            data,
+            ..self
+        }
+    }
+
+    fn to_dat(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(HEADER_LEN + self.data.len());
+        out.extend_from_slice(&self.version.to_le_bytes());
+        out.extend_from_slice(&FLAG_8_BIT.to_le_bytes());
+        out.extend_from_slice(&self.sample_rate.to_le_bytes());
+        out.extend_from_slice(&self.samples_per_pixel.to_le_bytes());
+        out.extend_from_slice(&self.length.to_le_bytes());
+        out.extend_from_slice(&self.channels.to_le_bytes());
+        out.extend(self.data.iter().map(|&b| b as u8));
+        out
+    }
+
+    fn from_dat(dat: &[u8]) -> Option<Waveform> {
+        let field = |i: usize| -> Option<u32> {
+            Some(u32::from_le_bytes(
+                dat.get(i * 4..i * 4 + 4)?.try_into().ok()?,
+            ))
+        };
+        let length = field(4)?;
+        let data = dat.get(HEADER_LEN..HEADER_LEN + length as usize * 2)?;
+        Some(Waveform {
+            version: DAT_VERSION,
+            channels: field(5)?,
+            sample_rate: field(2)?,
+            samples_per_pixel: field(3)?,
+            bits: 8,
+            length,
+            data: data.iter().map(|&b| b as i8).collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peaks(points: u32, frames: Option<u64>, samples: &[i32]) -> Waveform {
+        let mut peaks = Peaks::new(44100, points, frames);
+        for &sample in samples {
+            peaks.push(sample);
+        }
+        peaks.finish()
+    }
+
+    #[test]
+    fn dat_header_follows_audiowaveform() {
+        let dat = peaks(10, Some(1000), &[0; 1000]).to_dat();
+        let words: Vec<i32> = dat[..HEADER_LEN]
+            .chunks(4)
+            .map(|w| i32::from_le_bytes(w.try_into().unwrap()))
+            .collect();
+        // Version, flags, sample rate, samples per pixel, length, channels
+        assert_eq!(words, vec![2, 1, 44100, 100, 10, 1]);
+        assert_eq!(dat.len(), HEADER_LEN + 10 * 2);
+    }
+
+    #[test]
+    fn dat_round_trips() {
+        let samples: Vec<i32> = (0..4000).map(|i| (i * 37) % 65536 - 32768).collect();
+        let waveform = peaks(7, Some(4000), &samples);
+        assert_eq!(waveform.samples_per_pixel, 572);
+        assert_eq!(waveform.length, 7);
+        assert_eq!(Waveform::from_dat(&waveform.to_dat()), Some(waveform));
+    }
+
+    #[test]
+    fn peaks_are_min_max_per_pixel() {
+        let waveform = peaks(2, Some(4), &[512, -1024, 32767, -32768]);
+        assert_eq!(waveform.samples_per_pixel, 2);
+        assert_eq!(waveform.data, vec![-4, 2, -128, 127]);
+    }
+
+    #[test]
+    fn unknown_length_widens_pixels_as_it_goes() {
+        let samples: Vec<i32> = (0..1000).map(|i| i * 32).collect();
+        let waveform = peaks(10, None, &samples);
+        assert_eq!(waveform.samples_per_pixel, 128);
+        assert_eq!(waveform.length, 8);
+        // Every pixel covers the same span as with the length known up front
+        let known = peaks(8, Some(1024), &samples);
+        assert_eq!(known.samples_per_pixel, 128);
+        assert_eq!(waveform, known);
+    }
+
+    #[test]
+    fn short_tracks_get_fewer_points() {
+        let waveform = peaks(1000, Some(3), &[100; 3]);
+        assert_eq!(waveform.samples_per_pixel, 1);
+        assert_eq!(waveform.length, 3);
+    }
+
+    #[test]
+    fn downsampling_merges_neighbours() {
+        let samples: Vec<i32> = (0..1000).map(|i| (i % 7 - 3) * 4096).collect();
+        let waveform = peaks(100, Some(1000), &samples).downsample(30);
+        // 100 pairs four at a time
+        assert_eq!(waveform.samples_per_pixel, 40);
+        assert_eq!(waveform, peaks(25, Some(1000), &samples));
+    }
+
+    #[test]
+    fn downsampling_keeps_smaller_waveforms() {
+        let waveform = peaks(10, Some(100), &[0; 100]);
+        assert_eq!(waveform.downsample(1000), peaks(10, Some(100), &[0; 100]));
+    }
+
+    #[test]
+    fn truncated_dat_is_rejected() {
+        let dat = peaks(10, Some(100), &[0; 100]).to_dat();
+        assert!(Waveform::from_dat(&dat[..dat.len() - 1]).is_none());
+        assert!(Waveform::from_dat(&dat[..HEADER_LEN - 1]).is_none());
+    }
+}